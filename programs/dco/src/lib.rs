@@ -1,6 +1,14 @@
+// Anchor 0.31 emits its IDL instruction handlers outside the `#[program]` module and they
+// call the deprecated AccountInfo::realloc, so this allow cannot be scoped to that module.
+#![allow(deprecated)]
 
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_spl::metadata::mpl_token_metadata::accounts::Metadata;
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token::{self, CloseAccount, Mint, Token, TokenAccount, Transfer};
 use std::convert::TryFrom;
 
 
@@ -11,25 +19,72 @@ pub mod dco {
     
 
     /// Initialize DCO: creates `state` PDA and `vault` token account (authority = state PDA).
-    /// - `token_price`: u128 on-chain price (client must pass BN)
+    /// - `token_price`: quote base units per whole zktc, lamports until `set_quote_mint` (client must pass BN)
     /// - `dco_end_time`: i64 unix timestamp (client must pass BN)
     /// - `zcw`: Pubkey of charity/token-account owner (used later in donate_to_zcw)
+    /// - `soft_cap` / `hard_cap`: funding goal and ceiling in quote base units raised through `buy`
+    /// - `vesting_cliff` / `vesting_duration`: seconds after `dco_end_time` (TGE) before linear
+    ///   vesting starts, and the length of the linear vesting
+    /// - `tge_unlock_bps`: share of each allocation claimable at TGE, in basis points
+    /// - `donation_bps`: ZCW donation share of every sale, in basis points
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize(
         ctx: Context<Initialize>,
         token_price: u128,
         dco_end_time: i64,
         zcw: Pubkey,
+        soft_cap: u64,
+        hard_cap: u64,
+        vesting_cliff: i64,
+        vesting_duration: i64,
+        tge_unlock_bps: u16,
+        donation_bps: u16,
     ) -> Result<()> {
         require!(token_price > 0, DcoError::TokenPriceMustBeGreaterThanZero);
         require!(
             dco_end_time > Clock::get()?.unix_timestamp,
             DcoError::DcoNotActive
         );
+        require!(hard_cap > 0 && soft_cap <= hard_cap, DcoError::InvalidCaps);
+        require!(
+            vesting_cliff >= 0
+                && vesting_duration >= 0
+                && tge_unlock_bps as u64 <= DcoState::BPS_DENOMINATOR,
+            DcoError::InvalidVestingSchedule
+        );
+        require!(
+            donation_bps as u64 <= DcoState::BPS_DENOMINATOR,
+            DcoError::InvalidDonationBps
+        );
 
         let state = &mut ctx.accounts.state;
 
         // set core fields
         state.owner = ctx.accounts.owner.key();
+        state.seed_key = ctx.accounts.owner.key();
+        state.pending_owner = Pubkey::default();
+        state.guardian = Pubkey::default();
+        state.paused = false;
+        state.beneficiaries = Vec::new();
+        state.pricing_mode = PricingMode::Fixed;
+        state.referral_bps = 0;
+        state.total_referral_accrued = 0u128;
+        state.total_referral_claimed = 0u128;
+        state.kyc_issuer = Pubkey::default();
+        state.zktc_decimals = ctx.accounts.token_mint.decimals;
+        state.quote_mint = Pubkey::default();
+        state.quote_decimals = DcoState::NATIVE_SOL_DECIMALS;
+        state.quote_treasury = Pubkey::default();
+        state.price_feed = Pubkey::default();
+        state.max_price_age = 0;
+        state.max_price_conf_bps = 0;
+        state.fair_launch_supply = 0;
+        state.total_committed = 0;
+        state.total_commit_settled = 0;
+        state.settled = false;
+        state.settle_price = 0;
+        state.fair_launch_raised = 0;
+        state.fair_launch_reserved = 0;
         state.zk_token_mint = ctx.accounts.token_mint.key();
         state.vault = ctx.accounts.vault.key();
         state.token_price = token_price;
@@ -38,6 +93,20 @@ pub mod dco {
         state.dco_end_time = dco_end_time;
         state.zcw = zcw;
         state.releasers = Vec::new();
+        state.releaser_quotas = Vec::new();
+        state.soft_cap = soft_cap;
+        state.hard_cap = hard_cap;
+        state.total_raised = 0;
+        state.total_refunded = 0;
+        state.vesting_cliff = vesting_cliff;
+        state.vesting_duration = vesting_duration;
+        state.tge_unlock_bps = tge_unlock_bps;
+        state.total_vested = 0u128;
+        state.total_claimed = 0u128;
+        state.merkle_root = [0u8; 32];
+        state.round_count = 0;
+        state.last_round_end = 0;
+        state.donation_bps = donation_bps;
 
         // access bump directly from ctx.bumps struct
         state.bump = ctx.bumps.state;
@@ -49,6 +118,12 @@ pub mod dco {
             token_price,
             dco_end_time,
             zcw,
+            soft_cap,
+            hard_cap,
+            vesting_cliff,
+            vesting_duration,
+            tge_unlock_bps,
+            donation_bps,
         });
 
         Ok(())
@@ -57,6 +132,7 @@ pub mod dco {
     /// Owner transfers existing zktc tokens from `from` (owner's token account) into the vault.
     pub fn inject_supply(ctx: Context<InjectSupply>, amount: u64) -> Result<()> {
         require!(amount > 0, DcoError::AmountMustBeGreaterThanZero);
+        require!(!ctx.accounts.state.paused, DcoError::Paused);
 
         // transfer from owner -> vault (owner signs)
         let cpi_accounts = Transfer {
@@ -75,73 +151,597 @@ pub mod dco {
         Ok(())
     }
 
-    /// Release tokens from vault to buyer_token_account (PDA signs). Caller must be owner or a releaser.
-    /// donation_amount is accounted to `total_donations` but not immediately transferred out of the vault.
-   pub fn release_zktc(ctx: Context<ReleaseZktc>, amount: u64, donation_amount: u64) -> Result<()> {
-    require!(amount > 0, DcoError::AmountMustBeGreaterThanZero);
-    require!(donation_amount > 0, DcoError::AmountMustBeGreaterThanZero);
+    /// Release tokens to a buyer's vesting account. Caller must be owner or a releaser.
+    /// The tokens stay in the vault and are paid out over time by `claim_vested`.
+    /// The donation is derived from `amount` and `donation_bps`; only the owner may pass
+    /// `donation_override`. It is accounted to `total_donations` but not immediately transferred out of the vault.
+    /// When an allowlist is set, `proof` must prove `(buyer, max_allocation)` against `merkle_root`.
+    /// Once rounds exist, the currently active `round` must be passed and its allocation is enforced.
+    /// `payment_ref` identifies the off-chain payment; a receipt PDA seeded by it makes retries fail
+    /// with `TransactionAlreadyProcessed` instead of releasing twice.
+    /// An optional `referral` account is credited `referral_bps` of `amount`.
+    /// When `kyc_issuer` is set, the buyer must hold a soulbound credential from it.
+    pub fn release_zktc(
+        ctx: Context<ReleaseZktc>,
+        payment_ref: [u8; 32],
+        amount: u64,
+        donation_override: Option<u64>,
+        max_allocation: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(amount > 0, DcoError::AmountMustBeGreaterThanZero);
+        require!(!ctx.accounts.state.paused, DcoError::Paused);
 
-    // Check if vault has sufficient tokens
-    require!(
-        amount <= ctx.accounts.vault.amount,
-        DcoError::InsufficientTokens
-    );
+        // the receipt is shared by every release of this payment reference
+        require!(
+            !ctx.accounts.receipt.processed,
+            DcoError::TransactionAlreadyProcessed
+        );
 
-    // Caller authorization
-    let state_key = ctx.accounts.state.owner;
-    require!(
-        ctx.accounts.caller.key() == state_key
-            || ctx.accounts.state.releasers.contains(&ctx.accounts.caller.key()),
-        DcoError::Unauthorized
-    );
+        // Caller authorization
+        let state_key = ctx.accounts.state.owner;
+        require!(
+            ctx.accounts.caller.key() == state_key
+                || ctx.accounts.state.releasers.contains(&ctx.accounts.caller.key()),
+            DcoError::Unauthorized
+        );
 
-    // build PDA signer seeds
-    let bump = ctx.accounts.state.bump;
-    let seeds = &[
-        b"dco_state".as_ref(),
-        state_key.as_ref(),
-        &[bump],
-    ];
-    let signer = &[&seeds[..]];
-
-    // --- do the transfer in a scope with only immutable borrow ---
-    {
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.vault.to_account_info(),
-            to: ctx.accounts.buyer_token_account.to_account_info(),
-            authority: ctx.accounts.state.to_account_info(), // immutable borrow
+        let donation_amount = match donation_override {
+            Some(donation) => {
+                require!(
+                    ctx.accounts.caller.key() == state_key,
+                    DcoError::Unauthorized
+                );
+                donation
+            }
+            None => donation_share(amount, ctx.accounts.state.donation_bps)?,
         };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, amount)?;
+
+        let now = Clock::get()?.unix_timestamp;
+
+        // releasers are bounded by their daily quota, the owner is not
+        if ctx.accounts.caller.key() != state_key {
+            let caller_key = ctx.accounts.caller.key();
+            ctx.accounts
+                .state
+                .consume_releaser_quota(&caller_key, amount, now)?;
+        }
+
+        let referral_bonus = match ctx.accounts.referral.as_ref() {
+            Some(referral) => {
+                require!(
+                    referral.referrer != ctx.accounts.buyer.key(),
+                    DcoError::SelfReferral
+                );
+                referral_share(amount, ctx.accounts.state.referral_bps)?
+            }
+            None => 0,
+        };
+
+        // Check if vault has sufficient tokens on top of what is already owed to vesting, referrers and the ZCW
+        let owed = ctx
+            .accounts
+            .state
+            .outstanding_obligations()
+            .checked_add(amount as u128)
+            .and_then(|o| o.checked_add(referral_bonus as u128))
+            .and_then(|o| o.checked_add(donation_amount as u128))
+            .ok_or(DcoError::MathOverflow)?;
+        require!(
+            owed <= ctx.accounts.vault.amount as u128,
+            DcoError::InsufficientTokens
+        );
+
+        let (_, allowlist_required) =
+            active_round_terms(&ctx.accounts.state, ctx.accounts.round.as_deref(), amount, now)?;
+
+        check_allowlist(
+            &ctx.accounts.state,
+            &ctx.accounts.contribution,
+            ctx.accounts.buyer.key(),
+            amount,
+            max_allocation,
+            &proof,
+            allowlist_required,
+        )?;
+
+        check_kyc(
+            ctx.accounts.state.kyc_issuer,
+            ctx.accounts.buyer.key(),
+            ctx.accounts.kyc_token_account.as_deref(),
+            ctx.accounts.kyc_metadata.as_deref().map(|metadata| &**metadata),
+        )?;
+
+        if let Some(round) = ctx.accounts.round.as_mut() {
+            round.record_sale(amount)?;
+        }
+
+        let state = &mut ctx.accounts.state;
+
+        state.token_sold = state
+            .token_sold
+            .checked_add(amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+
+        state.total_donations = state
+            .total_donations
+            .checked_add(donation_amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+
+        state.total_vested = state
+            .total_vested
+            .checked_add(amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.buyer = ctx.accounts.buyer.key();
+        vesting.state = state.key();
+        vesting.total_allocated = vesting
+            .total_allocated
+            .checked_add(amount)
+            .ok_or(DcoError::MathOverflow)?;
+        vesting.bump = ctx.bumps.vesting;
+
+        let contribution = &mut ctx.accounts.contribution;
+        contribution.buyer = ctx.accounts.buyer.key();
+        contribution.state = state.key();
+        contribution.tokens_released = contribution
+            .tokens_released
+            .checked_add(amount)
+            .ok_or(DcoError::MathOverflow)?;
+        contribution.bump = ctx.bumps.contribution;
+
+        let receipt = &mut ctx.accounts.receipt;
+        receipt.state = state.key();
+        receipt.payment_ref = payment_ref;
+        receipt.caller = ctx.accounts.caller.key();
+        receipt.buyer = ctx.accounts.buyer.key();
+        receipt.amount = amount;
+        receipt.donation_amount = donation_amount;
+        receipt.slot = Clock::get()?.slot;
+        receipt.processed = true;
+        receipt.bump = ctx.bumps.receipt;
+
+        if let Some(referral) = ctx.accounts.referral.as_mut() {
+            credit_referral(state, referral, referral_bonus)?;
+        }
+
+        emit!(ZktcReleased {
+            caller: ctx.accounts.caller.key(),
+            payment_ref,
+            buyer: ctx.accounts.buyer.key(),
+            amount,
+            donation_amount,
+        });
+
+        Ok(())
     }
 
-    // --- now borrow mutably AFTER CPI ---
-    let state = &mut ctx.accounts.state;
+    /// Self-service purchase: buyer pays up to `payment` quote base units into the treasury and is
+    /// allocated `tokens_for_quote(payment)` zktc in their vesting account atomically.
+    /// SOL goes to the treasury PDA; once a quote mint is set, `buyer_quote_account` pays into `quote_treasury`.
+    /// Only whole zktc base units are charged (rounded up); any remainder stays with the buyer.
+    /// The donation share is computed on-chain and accounted to `total_donations`.
+    /// Purchases stop once `hard_cap` has been raised; each buyer's
+    /// contribution is recorded in a per-buyer PDA so it can be refunded if the sale fails.
+    /// When an allowlist is set, `proof` must prove `(buyer, max_allocation)` against `merkle_root`.
+    /// Once rounds exist, the currently active `round` must be passed and sets the price;
+    /// otherwise the price comes from `pricing_mode`. The purchase fails if it exceeds `max_price`.
+    /// An optional `referral` account is credited `referral_bps` of the purchased amount.
+    /// When `kyc_issuer` is set, the buyer must hold a soulbound credential from it.
+    /// When `price_feed` is set, prices are in micro-USD and converted to lamports from the feed.
+    pub fn buy(
+        ctx: Context<Buy>,
+        payment: u64,
+        max_price: u128,
+        max_allocation: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(payment > 0, DcoError::AmountMustBeGreaterThanZero);
+        require!(!ctx.accounts.state.paused, DcoError::Paused);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now < ctx.accounts.state.dco_end_time,
+            DcoError::DcoNotActive
+        );
 
-    state.token_sold = state
-        .token_sold
-        .checked_add(amount as u128)
-        .ok_or(DcoError::MathOverflow)?;
+        let (_, allowlist_required) =
+            active_round_terms(&ctx.accounts.state, ctx.accounts.round.as_deref(), 0, now)?;
+        let zktc_decimals = ctx.accounts.state.zktc_decimals;
+        let (amount, token_price) = size_purchase(payment, zktc_decimals, |amount| {
+            let (price, _) =
+                active_round_terms(&ctx.accounts.state, ctx.accounts.round.as_deref(), amount, now)?;
+            pegged_price(&ctx.accounts.state, ctx.accounts.price_feed.as_ref(), price, now)
+        })?;
+        require!(token_price <= max_price, DcoError::SlippageExceeded);
+        require!(amount > 0, DcoError::PaymentTooSmall);
+        let cost = quote_for_tokens(amount, token_price, zktc_decimals)?;
 
-    state.total_donations = state
-        .total_donations
-        .checked_add(donation_amount as u128)
-        .ok_or(DcoError::MathOverflow)?;
+        let total_raised = ctx
+            .accounts
+            .state
+            .total_raised
+            .checked_add(cost)
+            .ok_or(DcoError::MathOverflow)?;
+        require!(
+            total_raised <= ctx.accounts.state.hard_cap,
+            DcoError::HardCapReached
+        );
 
-    emit!(ZktcReleased {
-        caller: ctx.accounts.caller.key(),
-        buyer: ctx.accounts.buyer_token_account.key(),
-        amount,
-        donation_amount,
-    });
+        let referral_bonus = match ctx.accounts.referral.as_ref() {
+            Some(referral) => {
+                require!(
+                    referral.referrer != ctx.accounts.buyer.key(),
+                    DcoError::SelfReferral
+                );
+                referral_share(amount, ctx.accounts.state.referral_bps)?
+            }
+            None => 0,
+        };
+        let donation_amount = donation_share(amount, ctx.accounts.state.donation_bps)?;
 
-    Ok(())
-}
+        let owed = ctx
+            .accounts
+            .state
+            .outstanding_obligations()
+            .checked_add(amount as u128)
+            .and_then(|o| o.checked_add(referral_bonus as u128))
+            .and_then(|o| o.checked_add(donation_amount as u128))
+            .ok_or(DcoError::MathOverflow)?;
+        require!(
+            owed <= ctx.accounts.vault.amount as u128,
+            DcoError::InsufficientTokens
+        );
+
+        check_allowlist(
+            &ctx.accounts.state,
+            &ctx.accounts.contribution,
+            ctx.accounts.buyer.key(),
+            amount,
+            max_allocation,
+            &proof,
+            allowlist_required,
+        )?;
+
+        check_kyc(
+            ctx.accounts.state.kyc_issuer,
+            ctx.accounts.buyer.key(),
+            ctx.accounts.kyc_token_account.as_deref(),
+            ctx.accounts.kyc_metadata.as_deref().map(|metadata| &**metadata),
+        )?;
+
+        if let Some(round) = ctx.accounts.round.as_mut() {
+            round.record_sale(amount)?;
+        }
+
+        // buyer -> treasury (buyer signs)
+        if ctx.accounts.state.is_native_quote() {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.buyer.to_account_info(),
+                to: ctx.accounts.treasury.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            system_program::transfer(CpiContext::new(cpi_program, cpi_accounts), cost)?;
+        } else {
+            let (Some(buyer_quote_account), Some(quote_treasury)) = (
+                ctx.accounts.buyer_quote_account.as_ref(),
+                ctx.accounts.quote_treasury.as_ref(),
+            ) else {
+                return err!(DcoError::QuoteAccountsRequired);
+            };
+
+            let cpi_accounts = Transfer {
+                from: buyer_quote_account.to_account_info(),
+                to: quote_treasury.to_account_info(),
+                authority: ctx.accounts.buyer.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            token::transfer(CpiContext::new(cpi_program, cpi_accounts), cost)?;
+        }
+
+        let state = &mut ctx.accounts.state;
+
+        state.token_sold = state
+            .token_sold
+            .checked_add(amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+
+        state.total_donations = state
+            .total_donations
+            .checked_add(donation_amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+
+        state.total_raised = total_raised;
+
+        state.total_vested = state
+            .total_vested
+            .checked_add(amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.buyer = ctx.accounts.buyer.key();
+        vesting.state = state.key();
+        vesting.total_allocated = vesting
+            .total_allocated
+            .checked_add(amount)
+            .ok_or(DcoError::MathOverflow)?;
+        vesting.bump = ctx.bumps.vesting;
+
+        let contribution = &mut ctx.accounts.contribution;
+        contribution.buyer = ctx.accounts.buyer.key();
+        contribution.state = state.key();
+        contribution.contributed = contribution
+            .contributed
+            .checked_add(cost)
+            .ok_or(DcoError::MathOverflow)?;
+        contribution.tokens_purchased = contribution
+            .tokens_purchased
+            .checked_add(amount)
+            .ok_or(DcoError::MathOverflow)?;
+        contribution.donations = contribution
+            .donations
+            .checked_add(donation_amount)
+            .ok_or(DcoError::MathOverflow)?;
+        contribution.bump = ctx.bumps.contribution;
+
+        if let Some(referral) = ctx.accounts.referral.as_mut() {
+            credit_referral(state, referral, referral_bonus)?;
+        }
+
+        emit!(ZktcPurchased {
+            buyer: ctx.accounts.buyer.key(),
+            amount,
+            paid: cost,
+            donation_amount,
+        });
+
+        Ok(())
+    }
+
+    /// Refund a buyer's contribution when the sale ended below `soft_cap`.
+    /// The purchased tokens are removed from the buyer's vesting allocation and the
+    /// contribution is paid back from the treasury: SOL from the treasury PDA, or the
+    /// quote mint from `quote_treasury` to `buyer_quote_account` (state PDA signs).
+    /// Called by the buyer, or by the owner to refund absent buyers before `finalize`.
+    pub fn claim_refund(ctx: Context<ClaimRefund>) -> Result<()> {
+        let state = &ctx.accounts.state;
+        // fair-launch commitments count towards `soft_cap` only once settled
+        require!(
+            state.total_committed == 0 || state.settled,
+            DcoError::FairLaunchNotSettled
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= state.dco_end_time
+                && state.total_raised < state.soft_cap,
+            DcoError::RefundNotAvailable
+        );
+
+        let contribution = &ctx.accounts.contribution;
+        require!(contribution.contributed > 0, DcoError::NothingToRefund);
+
+        let refund = contribution.contributed;
+        let tokens = contribution.tokens_purchased;
+        let donations = contribution.donations;
+
+        if state.is_native_quote() {
+            // treasury -> buyer (treasury PDA signs)
+            let state_key = ctx.accounts.state.key();
+            let bump = ctx.bumps.treasury;
+            let seeds = &[
+                b"dco_treasury".as_ref(),
+                state_key.as_ref(),
+                &[bump],
+            ];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.treasury.to_account_info(),
+                to: ctx.accounts.buyer.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            system_program::transfer(cpi_ctx, refund)?;
+        } else {
+            let (Some(buyer_quote_account), Some(quote_treasury), Some(token_program)) = (
+                ctx.accounts.buyer_quote_account.as_ref(),
+                ctx.accounts.quote_treasury.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+            ) else {
+                return err!(DcoError::QuoteAccountsRequired);
+            };
+
+            // quote_treasury -> buyer (state PDA signs)
+            let seed_key = state.seed_key;
+            let bump = state.bump;
+            let seeds = &[
+                b"dco_state".as_ref(),
+                seed_key.as_ref(),
+                &[bump],
+            ];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = Transfer {
+                from: quote_treasury.to_account_info(),
+                to: buyer_quote_account.to_account_info(),
+                authority: ctx.accounts.state.to_account_info(),
+            };
+            let cpi_program = token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, refund)?;
+        }
+
+        let state = &mut ctx.accounts.state;
+        state.token_sold = state
+            .token_sold
+            .checked_sub(tokens as u128)
+            .ok_or(DcoError::MathOverflow)?;
+        // donations may already have been flushed by donate_to_zcw
+        state.total_donations = state.total_donations.saturating_sub(donations as u128);
+        state.total_refunded = state
+            .total_refunded
+            .checked_add(refund)
+            .ok_or(DcoError::MathOverflow)?;
+        state.total_vested = state
+            .total_vested
+            .checked_sub(tokens as u128)
+            .ok_or(DcoError::MathOverflow)?;
+
+        // claim_vested is blocked in refund mode, so nothing of this allocation was paid out
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.total_allocated = vesting
+            .total_allocated
+            .checked_sub(tokens)
+            .ok_or(DcoError::MathOverflow)?;
+
+        let contribution = &mut ctx.accounts.contribution;
+        contribution.contributed = 0;
+        contribution.tokens_purchased = 0;
+        contribution.donations = 0;
+
+        emit!(RefundClaimed {
+            buyer: ctx.accounts.buyer.key(),
+            amount: refund,
+            tokens_cancelled: tokens,
+        });
+
+        Ok(())
+    }
+
+    /// Buyer claims the vested-but-unclaimed part of their allocation from the vault (PDA signs).
+    /// Vesting starts at `dco_end_time` (TGE); nothing is claimable while the sale is in refund mode.
+    pub fn claim_vested(ctx: Context<ClaimVested>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let state = &ctx.accounts.state;
+        require!(!state.sale_failed(now), DcoError::SoftCapNotReached);
+
+        let vesting = &ctx.accounts.vesting;
+        let vested = state.vested_amount(vesting.total_allocated, now)?;
+        let amount = vested
+            .checked_sub(vesting.claimed)
+            .ok_or(DcoError::MathOverflow)?;
+        require!(amount > 0, DcoError::NothingToClaim);
+        require!(
+            amount <= ctx.accounts.vault.amount,
+            DcoError::InsufficientTokens
+        );
+
+        let seed_key = state.seed_key;
+        let bump = state.bump;
+        let seeds = &[
+            b"dco_state".as_ref(),
+            seed_key.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.vault.to_account_info(),
+                to: ctx.accounts.buyer_token_account.to_account_info(),
+                authority: ctx.accounts.state.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, amount)?;
+        }
+
+        let state = &mut ctx.accounts.state;
+        state.total_claimed = state
+            .total_claimed
+            .checked_add(amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.claimed = vested;
+
+        emit!(VestedClaimed {
+            buyer: ctx.accounts.buyer.key(),
+            amount,
+            total_claimed: vested,
+        });
+
+        Ok(())
+    }
+
+    /// Create the caller's referral account so buyers can name them as referrer.
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referral = &mut ctx.accounts.referral;
+        referral.referrer = ctx.accounts.referrer.key();
+        referral.state = ctx.accounts.state.key();
+        referral.accrued = 0;
+        referral.claimed = 0;
+        referral.bump = ctx.bumps.referral;
+
+        emit!(ReferrerRegistered {
+            referrer: referral.referrer,
+        });
+        Ok(())
+    }
+
+    /// Referrer claims the vested part of their accrued referral bonuses from the vault (PDA signs).
+    /// Only allowed once the sale is successful; bonuses follow the buyers' vesting schedule
+    /// (see `DcoState::vested_amount`), since refunds do not claw back accrued bonuses.
+    pub fn claim_referral(ctx: Context<ClaimReferral>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let state = &ctx.accounts.state;
+        require!(state.sale_succeeded(now), DcoError::SoftCapNotReached);
+
+        let referral = &ctx.accounts.referral;
+        let vested = state.vested_amount(referral.accrued, now)?;
+        let amount = vested
+            .checked_sub(referral.claimed)
+            .ok_or(DcoError::MathOverflow)?;
+        require!(amount > 0, DcoError::NothingToClaim);
+        require!(
+            amount <= ctx.accounts.vault.amount,
+            DcoError::InsufficientTokens
+        );
+
+        let seed_key = state.seed_key;
+        let bump = state.bump;
+        let seeds = &[
+            b"dco_state".as_ref(),
+            seed_key.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.vault.to_account_info(),
+                to: ctx.accounts.referrer_token_account.to_account_info(),
+                authority: ctx.accounts.state.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, amount)?;
+        }
+
+        let state = &mut ctx.accounts.state;
+        state.total_referral_claimed = state
+            .total_referral_claimed
+            .checked_add(amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+
+        let referral = &mut ctx.accounts.referral;
+        referral.claimed = vested;
+
+        emit!(ReferralClaimed {
+            referrer: ctx.accounts.referrer.key(),
+            amount,
+        });
+
+        Ok(())
+    }
 
-    /// Transfer the accumulated donations from vault -> zcw_token_account (signed by PDA).
+    /// Transfer the accumulated donations from the vault (signed by PDA).
+    /// Without a beneficiary table everything goes to `zcw_token_account`, which must belong to `zcw`.
+    /// Otherwise donations are split by weight; pass each beneficiary's token account as a
+    /// remaining account, in table order. The last beneficiary receives the rounding remainder.
     /// After success, reset total_donations to 0.
-    pub fn donate_to_zcw(ctx: Context<DonateToZcw>) -> Result<()> {
+    pub fn donate_to_zcw<'info>(ctx: Context<'_, '_, 'info, 'info, DonateToZcw<'info>>) -> Result<()> {
+        require!(!ctx.accounts.state.paused, DcoError::Paused);
+
         // authorization: owner or releaser
         require!(
             ctx.accounts.caller.key() == ctx.accounts.state.owner
@@ -153,58 +753,135 @@ pub mod dco {
 
         // Get values we need before the transfer
         let donation_amount = ctx.accounts.state.total_donations;
-        let owner_key = ctx.accounts.state.owner;
+        let seed_key = ctx.accounts.state.seed_key;
         let bump = ctx.accounts.state.bump;
         let caller_key = ctx.accounts.caller.key();
-        let zcw_key = ctx.accounts.zcw_token_account.key();
 
         // convert u128 -> u64 for SPL transfer
         let transfer_amount = u64::try_from(donation_amount).map_err(|_| DcoError::AmountTooLarge)?;
 
-        // Check if vault has sufficient tokens for donation
+        // the donation may only come out of tokens not owed to anyone else
+        let owed = ctx
+            .accounts
+            .state
+            .outstanding_obligations()
+            .saturating_sub(donation_amount)
+            .checked_add(transfer_amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
         require!(
-            transfer_amount <= ctx.accounts.vault.amount,
+            owed <= ctx.accounts.vault.amount as u128,
             DcoError::InsufficientTokens
         );
 
         // build signer using same PDA seeds
         let seeds = &[
             b"dco_state".as_ref(),
-            owner_key.as_ref(),
+            seed_key.as_ref(),
             &[bump],
         ];
         let signer = &[&seeds[..]];
 
-        // Perform transfer in a scope to end the immutable borrow
-        {
+        let beneficiaries = ctx.accounts.state.beneficiaries.clone();
+        if beneficiaries.is_empty() {
+            let zcw_token_account = ctx
+                .accounts
+                .zcw_token_account
+                .as_ref()
+                .ok_or(DcoError::InvalidAddress)?;
+            require!(
+                zcw_token_account.owner == ctx.accounts.state.zcw,
+                DcoError::InvalidAddress
+            );
+
             let cpi_accounts = Transfer {
                 from: ctx.accounts.vault.to_account_info(),
-                to: ctx.accounts.zcw_token_account.to_account_info(),
+                to: zcw_token_account.to_account_info(),
                 authority: ctx.accounts.state.to_account_info(),
             };
             let cpi_program = ctx.accounts.token_program.to_account_info();
             let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
             token::transfer(cpi_ctx, transfer_amount)?;
+
+            emit!(DonationSent {
+                caller: caller_key,
+                zcw: zcw_token_account.key(),
+                amount: transfer_amount,
+            });
+        } else {
+            require!(
+                ctx.remaining_accounts.len() == beneficiaries.len(),
+                DcoError::InvalidBeneficiaryAccounts
+            );
+
+            let mut remaining = transfer_amount;
+            for (i, (beneficiary, account_info)) in beneficiaries
+                .iter()
+                .zip(ctx.remaining_accounts.iter())
+                .enumerate()
+            {
+                let token_account = Account::<TokenAccount>::try_from(account_info)?;
+                require!(
+                    token_account.mint == ctx.accounts.state.zk_token_mint
+                        && token_account.owner == beneficiary.wallet,
+                    DcoError::InvalidBeneficiaryAccounts
+                );
+
+                let share = if i == beneficiaries.len() - 1 {
+                    remaining
+                } else {
+                    ((transfer_amount as u128) * (beneficiary.weight_bps as u128)
+                        / DcoState::BPS_DENOMINATOR as u128) as u64
+                };
+                remaining = remaining
+                    .checked_sub(share)
+                    .ok_or(DcoError::MathOverflow)?;
+
+                if share > 0 {
+                    let cpi_accounts = Transfer {
+                        from: ctx.accounts.vault.to_account_info(),
+                        to: account_info.clone(),
+                        authority: ctx.accounts.state.to_account_info(),
+                    };
+                    let cpi_program = ctx.accounts.token_program.to_account_info();
+                    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+                    token::transfer(cpi_ctx, share)?;
+                }
+
+                emit!(BeneficiaryDonationSent {
+                    caller: caller_key,
+                    beneficiary: beneficiary.wallet,
+                    token_account: account_info.key(),
+                    weight_bps: beneficiary.weight_bps,
+                    amount: share,
+                });
+            }
         }
 
         // Now we can mutably borrow state to reset donations
         let state = &mut ctx.accounts.state;
         state.total_donations = 0u128;
 
-        emit!(DonationSent {
-            caller: caller_key,
-            zcw: zcw_key,
-            amount: transfer_amount,
-        });
-
         Ok(())
     }
 
     /// Owner withdraws remaining tokens after DCO end time (signed by PDA).
+    /// Blocked while the sale is below `soft_cap` so refunds can be honoured.
     pub fn withdraw(ctx: Context<OnlyOwnerAfterEnd>, amount: u64) -> Result<()> {
         require!(amount > 0, DcoError::AmountMustBeGreaterThanZero);
         require!(
-            amount <= ctx.accounts.vault.amount,
+            ctx.accounts.state.total_raised >= ctx.accounts.state.soft_cap,
+            DcoError::SoftCapNotReached
+        );
+
+        // tokens owed to vesting accounts, referrers and the ZCW are not withdrawable
+        let owed = ctx
+            .accounts
+            .state
+            .outstanding_obligations()
+            .checked_add(amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+        require!(
+            owed <= ctx.accounts.vault.amount as u128,
             DcoError::InsufficientTokens
         );
 
@@ -212,7 +889,7 @@ pub mod dco {
         let bump = state.bump;
         let seeds = &[
             b"dco_state".as_ref(),
-            state.owner.as_ref(),
+            state.seed_key.as_ref(),
             &[bump],
         ];
         let signer = &[&seeds[..]];
@@ -234,178 +911,2539 @@ pub mod dco {
         Ok(())
     }
 
-    /// Add a new releaser (owner-only)
-    pub fn add_releaser(ctx: Context<OnlyOwner>, new_releaser: Pubkey) -> Result<()> {
-        let state = &mut ctx.accounts.state;
-
+    /// Owner withdraws payments collected by `buy`: SOL from the treasury PDA (signed by treasury PDA),
+    /// or the quote mint from `quote_treasury` to `owner_quote_account` (signed by state PDA).
+    /// Only allowed once the sale is successful (see `DcoState::sale_succeeded`).
+    /// Deposits of unclaimed fair-launch commitments stay in the treasury as a reserve
+    /// (see `DcoState::commitment_reserve`).
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+        require!(amount > 0, DcoError::AmountMustBeGreaterThanZero);
         require!(
-            state.releasers.len() < DcoState::MAX_RELEASERS,
-            DcoError::ReleasersFull
+            ctx.accounts
+                .state
+                .sale_succeeded(Clock::get()?.unix_timestamp),
+            DcoError::SoftCapNotReached
         );
+        // unclaimed commitments are refunded from the treasury
+        let required = amount
+            .checked_add(ctx.accounts.state.commitment_reserve())
+            .ok_or(DcoError::MathOverflow)?;
 
-        if !state.releasers.contains(&new_releaser) {
-            state.releasers.push(new_releaser);
+        if ctx.accounts.state.is_native_quote() {
+            require!(
+                required <= ctx.accounts.treasury.lamports(),
+                DcoError::InsufficientBalance
+            );
+
+            let state_key = ctx.accounts.state.key();
+            let bump = ctx.bumps.treasury;
+            let seeds = &[
+                b"dco_treasury".as_ref(),
+                state_key.as_ref(),
+                &[bump],
+            ];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.treasury.to_account_info(),
+                to: ctx.accounts.owner.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            system_program::transfer(cpi_ctx, amount)?;
+        } else {
+            let (Some(owner_quote_account), Some(quote_treasury), Some(token_program)) = (
+                ctx.accounts.owner_quote_account.as_ref(),
+                ctx.accounts.quote_treasury.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+            ) else {
+                return err!(DcoError::QuoteAccountsRequired);
+            };
+            require!(
+                required <= quote_treasury.amount,
+                DcoError::InsufficientBalance
+            );
+
+            let seed_key = ctx.accounts.state.seed_key;
+            let bump = ctx.accounts.state.bump;
+            let seeds = &[
+                b"dco_state".as_ref(),
+                seed_key.as_ref(),
+                &[bump],
+            ];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = Transfer {
+                from: quote_treasury.to_account_info(),
+                to: owner_quote_account.to_account_info(),
+                authority: ctx.accounts.state.to_account_info(),
+            };
+            let cpi_program = token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, amount)?;
         }
 
-        emit!(ReleaserAdded {
+        emit!(TreasuryWithdrawn {
             owner: ctx.accounts.owner.key(),
-            new_releaser,
+            quote_mint: ctx.accounts.state.quote_mint,
+            amount,
         });
+
         Ok(())
     }
 
-    /// Remove a releaser (owner-only)
-    pub fn remove_releaser(ctx: Context<OnlyOwner>, old_releaser: Pubkey) -> Result<()> {
+    /// Switch sale payments from SOL to an SPL quote mint (wSOL or a USDC-like mint), owner-only.
+    /// Creates the PDA-owned `quote_treasury` token account; only possible before anything is sold.
+    /// `token_price`, caps and pricing modes are then read in the quote mint's base units.
+    pub fn set_quote_mint(ctx: Context<SetQuoteMint>) -> Result<()> {
         let state = &mut ctx.accounts.state;
-        state.releasers.retain(|r| *r != old_releaser);
+        require!(
+            state.total_raised == 0 && state.token_sold == 0,
+            DcoError::SaleAlreadyStarted
+        );
+        // the USD peg only converts into lamports
+        require!(
+            state.price_feed == Pubkey::default(),
+            DcoError::InvalidQuoteMint
+        );
 
-        emit!(ReleaserRemoved {
+        state.quote_mint = ctx.accounts.quote_mint.key();
+        state.quote_decimals = ctx.accounts.quote_mint.decimals;
+        state.quote_treasury = ctx.accounts.quote_treasury.key();
+
+        emit!(QuoteMintSet {
             owner: ctx.accounts.owner.key(),
-            old_releaser,
+            quote_mint: state.quote_mint,
+            quote_decimals: state.quote_decimals,
+            quote_treasury: state.quote_treasury,
         });
         Ok(())
     }
 
-}
-
-//
-// --- Accounts / State types ---
-//
-
-#[derive(Accounts)]
-#[instruction(token_price: u128, dco_end_time: i64, zcw: Pubkey)]
-pub struct Initialize<'info> {
-    /// Owner who creates the DCO and pays for account creation
-    #[account(mut)]
-    pub owner: Signer<'info>,
+    /// Enable the commit-then-settle fair launch for `supply` zktc base units (owner-only, before any sale).
+    /// Pass 0 to disable it.
+    pub fn set_fair_launch(ctx: Context<OnlyOwner>, supply: u64) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        require!(
+            state.token_sold == 0 && state.total_committed == 0,
+            DcoError::SaleAlreadyStarted
+        );
+        state.fair_launch_supply = supply;
 
-    /// The zktc token mint (must be an existing Mint)
-    pub token_mint: Account<'info, Mint>,
+        emit!(FairLaunchConfigured {
+            owner: ctx.accounts.owner.key(),
+            supply,
+        });
+        Ok(())
+    }
+
+    /// Fair launch: deposit `amount` quote base units into the buyer's commitment PDA
+    /// while the sale is active. Payment goes to the same treasury as `buy`.
+    /// When `kyc_issuer` is set, the buyer must hold a soulbound credential from it.
+    /// Not available with an allowlist or sale rounds, whose caps and prices apply per purchase.
+    /// A `referral` passed on the first referred commit earns the referrer a bonus on the fill.
+    pub fn commit(ctx: Context<Commit>, amount: u64) -> Result<()> {
+        require!(amount > 0, DcoError::AmountMustBeGreaterThanZero);
+        let state = &ctx.accounts.state;
+        require!(!state.paused, DcoError::Paused);
+        require!(state.fair_launch_supply > 0, DcoError::FairLaunchNotEnabled);
+        require!(
+            Clock::get()?.unix_timestamp < state.dco_end_time,
+            DcoError::DcoNotActive
+        );
+        require!(
+            state.merkle_root == [0u8; 32] && state.round_count == 0,
+            DcoError::FairLaunchRestricted
+        );
+
+        check_kyc(
+            state.kyc_issuer,
+            ctx.accounts.buyer.key(),
+            ctx.accounts.kyc_token_account.as_deref(),
+            ctx.accounts.kyc_metadata.as_deref().map(|metadata| &**metadata),
+        )?;
+
+        if let Some(referral) = ctx.accounts.referral.as_ref() {
+            require!(
+                referral.referrer != ctx.accounts.buyer.key(),
+                DcoError::SelfReferral
+            );
+        }
+
+        // buyer -> treasury (buyer signs)
+        if state.is_native_quote() {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.buyer.to_account_info(),
+                to: ctx.accounts.treasury.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            system_program::transfer(CpiContext::new(cpi_program, cpi_accounts), amount)?;
+        } else {
+            let (Some(buyer_quote_account), Some(quote_treasury)) = (
+                ctx.accounts.buyer_quote_account.as_ref(),
+                ctx.accounts.quote_treasury.as_ref(),
+            ) else {
+                return err!(DcoError::QuoteAccountsRequired);
+            };
+
+            let cpi_accounts = Transfer {
+                from: buyer_quote_account.to_account_info(),
+                to: quote_treasury.to_account_info(),
+                authority: ctx.accounts.buyer.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            token::transfer(CpiContext::new(cpi_program, cpi_accounts), amount)?;
+        }
+
+        let state = &mut ctx.accounts.state;
+        state.total_committed = state
+            .total_committed
+            .checked_add(amount)
+            .ok_or(DcoError::MathOverflow)?;
+
+        let commitment = &mut ctx.accounts.commitment;
+        commitment.buyer = ctx.accounts.buyer.key();
+        commitment.state = state.key();
+        commitment.committed = commitment
+            .committed
+            .checked_add(amount)
+            .ok_or(DcoError::MathOverflow)?;
+        if commitment.referral == Pubkey::default() {
+            if let Some(referral) = ctx.accounts.referral.as_ref() {
+                commitment.referral = referral.key();
+            }
+        }
+        commitment.bump = ctx.bumps.commitment;
+
+        emit!(Committed {
+            buyer: ctx.accounts.buyer.key(),
+            amount,
+            total_committed: state.total_committed,
+        });
+
+        Ok(())
+    }
+
+    /// Fair launch: close the commit window after `dco_end_time` (permissionless).
+    /// Fixes the settle price, adds the filled value to `total_raised` and reserves the
+    /// filled zktc in the vault. Pass the `price_feed` when pegged.
+    pub fn settle(ctx: Context<Settle>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let state = &ctx.accounts.state;
+        require!(state.fair_launch_supply > 0, DcoError::FairLaunchNotEnabled);
+        require!(!state.settled, DcoError::FairLaunchAlreadySettled);
+        require!(now >= state.dco_end_time, DcoError::DcoNotActive);
+
+        let price_at = |amount| {
+            pegged_price(
+                state,
+                ctx.accounts.price_feed.as_ref(),
+                state.purchase_price(amount, now)?,
+                now,
+            )
+        };
+        let (sold, _) = size_purchase(state.total_committed, state.zktc_decimals, price_at)?;
+        let sold = sold.min(state.fair_launch_supply);
+        let price = price_at(sold)?;
+        let raised = quote_for_tokens(sold, price, state.zktc_decimals)?;
+
+        let state = &mut ctx.accounts.state;
+        state.settled = true;
+        state.settle_price = price;
+        state.fair_launch_raised = raised;
+        state.total_raised = state
+            .total_raised
+            .checked_add(raised)
+            .ok_or(DcoError::MathOverflow)?;
+
+        // a failed sale refunds every commitment in full
+        if !state.sale_failed(now) {
+            // fills plus their donation share and, should every buyer be referred, referral bonuses
+            let donation = donation_share(sold, state.donation_bps)?;
+            let referral_bonus = referral_share(sold, state.referral_bps)?;
+            let reserved = sold
+                .checked_add(donation)
+                .and_then(|r| r.checked_add(referral_bonus))
+                .ok_or(DcoError::MathOverflow)?;
+            let owed = state
+                .outstanding_obligations()
+                .checked_add(reserved as u128)
+                .ok_or(DcoError::MathOverflow)?;
+            require!(
+                owed <= ctx.accounts.vault.amount as u128,
+                DcoError::InsufficientTokens
+            );
+            state.fair_launch_reserved = reserved;
+        }
+
+        emit!(FairLaunchSettled {
+            caller: ctx.accounts.caller.key(),
+            price,
+            total_committed: state.total_committed,
+            tokens_reserved: state.fair_launch_reserved,
+            raised,
+        });
+
+        Ok(())
+    }
+
+    /// Fair launch: after `settle`, credit the buyer's pro-rata fill (see `fair_launch_allocation`)
+    /// to their vesting account, paid out by `claim_vested`, and refund the unused remainder of the
+    /// commitment from the treasury. The fill accrues its `donation_bps` share and, if the commitment
+    /// has a `referral`, a referral bonus. A failed sale refunds the whole commitment.
+    /// Permissionless, so the owner can settle commitments of buyers who never claim.
+    pub fn claim_commitment(ctx: Context<ClaimCommitment>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let state = &ctx.accounts.state;
+        require!(state.settled, DcoError::FairLaunchNotSettled);
+        require!(
+            !ctx.accounts.commitment.claimed,
+            DcoError::CommitmentAlreadyClaimed
+        );
+
+        let committed = ctx.accounts.commitment.committed;
+        let (tokens, cost) = if state.sale_failed(now) {
+            (0, 0)
+        } else {
+            fair_launch_allocation(
+                committed,
+                state.total_committed,
+                state.fair_launch_supply,
+                state.settle_price,
+                state.zktc_decimals,
+            )?
+        };
+        let refund = committed.checked_sub(cost).ok_or(DcoError::MathOverflow)?;
+        let donation_amount = donation_share(tokens, state.donation_bps)?;
+
+        let referral_bonus = if tokens > 0 && ctx.accounts.commitment.referral != Pubkey::default() {
+            let referral = ctx
+                .accounts
+                .referral
+                .as_ref()
+                .ok_or(DcoError::ReferralAccountRequired)?;
+            require!(
+                referral.key() == ctx.accounts.commitment.referral,
+                DcoError::ReferralAccountRequired
+            );
+            referral_share(tokens, state.referral_bps)?
+        } else {
+            0
+        };
+
+        let seed_key = state.seed_key;
+        let bump = state.bump;
+        let seeds = &[
+            b"dco_state".as_ref(),
+            seed_key.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        // treasury -> buyer
+        if refund > 0 {
+            if state.is_native_quote() {
+                let state_key = ctx.accounts.state.key();
+                let treasury_bump = ctx.bumps.treasury;
+                let treasury_seeds = &[
+                    b"dco_treasury".as_ref(),
+                    state_key.as_ref(),
+                    &[treasury_bump],
+                ];
+                let treasury_signer = &[&treasury_seeds[..]];
+
+                let cpi_accounts = system_program::Transfer {
+                    from: ctx.accounts.treasury.to_account_info(),
+                    to: ctx.accounts.buyer.to_account_info(),
+                };
+                let cpi_program = ctx.accounts.system_program.to_account_info();
+                let cpi_ctx =
+                    CpiContext::new_with_signer(cpi_program, cpi_accounts, treasury_signer);
+                system_program::transfer(cpi_ctx, refund)?;
+            } else {
+                let (Some(buyer_quote_account), Some(quote_treasury)) = (
+                    ctx.accounts.buyer_quote_account.as_ref(),
+                    ctx.accounts.quote_treasury.as_ref(),
+                ) else {
+                    return err!(DcoError::QuoteAccountsRequired);
+                };
+
+                let cpi_accounts = Transfer {
+                    from: quote_treasury.to_account_info(),
+                    to: buyer_quote_account.to_account_info(),
+                    authority: ctx.accounts.state.to_account_info(),
+                };
+                let cpi_program = ctx.accounts.token_program.to_account_info();
+                let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+                token::transfer(cpi_ctx, refund)?;
+            }
+        }
+
+        let state = &mut ctx.accounts.state;
+        state.token_sold = state
+            .token_sold
+            .checked_add(tokens as u128)
+            .ok_or(DcoError::MathOverflow)?;
+        state.total_vested = state
+            .total_vested
+            .checked_add(tokens as u128)
+            .ok_or(DcoError::MathOverflow)?;
+        state.total_donations = state
+            .total_donations
+            .checked_add(donation_amount as u128)
+            .ok_or(DcoError::MathOverflow)?;
+        if referral_bonus > 0 {
+            if let Some(referral) = ctx.accounts.referral.as_mut() {
+                credit_referral(state, referral, referral_bonus)?;
+            }
+        }
+        state.total_commit_settled = state
+            .total_commit_settled
+            .checked_add(committed)
+            .ok_or(DcoError::MathOverflow)?;
+        // rounding dust and unused referral reserve stay in the vault once every commitment is claimed
+        state.fair_launch_reserved = if state.total_commit_settled == state.total_committed {
+            0
+        } else {
+            state
+                .fair_launch_reserved
+                .saturating_sub(tokens)
+                .saturating_sub(donation_amount)
+                .saturating_sub(referral_bonus)
+        };
+
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.buyer = ctx.accounts.buyer.key();
+        vesting.state = state.key();
+        vesting.total_allocated = vesting
+            .total_allocated
+            .checked_add(tokens)
+            .ok_or(DcoError::MathOverflow)?;
+        vesting.bump = ctx.bumps.vesting;
+
+        ctx.accounts.commitment.claimed = true;
+
+        emit!(CommitmentClaimed {
+            buyer: ctx.accounts.buyer.key(),
+            tokens,
+            cost,
+            refund,
+        });
+
+        Ok(())
+    }
+
+    /// Set the ZCW charity wallet (owner-only)
+    pub fn set_zcw(ctx: Context<OnlyOwner>, new_zcw: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        let old_zcw = state.zcw;
+        state.zcw = new_zcw;
+
+        emit!(ZcwUpdated {
+            owner: ctx.accounts.owner.key(),
+            old_zcw,
+            new_zcw,
+        });
+        Ok(())
+    }
+
+    /// Push `dco_end_time` forward (owner-only). Only allowed while the sale is running,
+    /// and by at most `DcoState::MAX_END_TIME_EXTENSION` seconds per call.
+    pub fn extend_end_time(ctx: Context<OnlyOwner>, new_end_time: i64) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        let old_end_time = state.dco_end_time;
+
+        require!(
+            Clock::get()?.unix_timestamp < old_end_time,
+            DcoError::DcoNotActive
+        );
+        require!(new_end_time > old_end_time, DcoError::InvalidEndTime);
+        require!(
+            new_end_time - old_end_time <= DcoState::MAX_END_TIME_EXTENSION,
+            DcoError::ExtensionTooLarge
+        );
+
+        state.dco_end_time = new_end_time;
+
+        emit!(EndTimeExtended {
+            owner: ctx.accounts.owner.key(),
+            old_end_time,
+            new_end_time,
+        });
+        Ok(())
+    }
+
+    /// Set the sale-wide token price (owner-only). Free to change before anything is sold;
+    /// afterwards it may move by at most `DcoState::MAX_PRICE_DELTA_BPS` per call.
+    pub fn set_token_price(ctx: Context<OnlyOwner>, new_price: u128) -> Result<()> {
+        require!(new_price > 0, DcoError::TokenPriceMustBeGreaterThanZero);
+
+        let state = &mut ctx.accounts.state;
+        let old_price = state.token_price;
+
+        if state.token_sold > 0 {
+            let max_delta = old_price
+                .checked_mul(DcoState::MAX_PRICE_DELTA_BPS as u128)
+                .ok_or(DcoError::MathOverflow)?
+                / DcoState::BPS_DENOMINATOR as u128;
+            require!(
+                new_price.abs_diff(old_price) <= max_delta,
+                DcoError::PriceChangeTooLarge
+            );
+        }
+
+        state.token_price = new_price;
+
+        emit!(TokenPriceUpdated {
+            owner: ctx.accounts.owner.key(),
+            old_price,
+            new_price,
+        });
+        Ok(())
+    }
+
+    /// Set the referral bonus rate in basis points of referred amounts (owner-only)
+    pub fn set_referral_bps(ctx: Context<OnlyOwner>, referral_bps: u16) -> Result<()> {
+        require!(
+            referral_bps as u64 <= DcoState::BPS_DENOMINATOR,
+            DcoError::InvalidReferralBps
+        );
+
+        let state = &mut ctx.accounts.state;
+        let old_bps = state.referral_bps;
+        state.referral_bps = referral_bps;
+
+        emit!(ReferralBpsUpdated {
+            owner: ctx.accounts.owner.key(),
+            old_bps,
+            new_bps: referral_bps,
+        });
+        Ok(())
+    }
+
+    /// Set the accepted KYC credential issuer (owner-only). Credentials are soulbound NFTs
+    /// in this verified collection, or with this key as a verified creator. NFTs minted by
+    /// `soul_bound::mint_soulbound_nft` qualify with the minting payer as the issuer.
+    /// `Pubkey::default()` disables the KYC check.
+    pub fn set_kyc_issuer(ctx: Context<OnlyOwner>, kyc_issuer: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        let old_issuer = state.kyc_issuer;
+        state.kyc_issuer = kyc_issuer;
+
+        emit!(KycIssuerUpdated {
+            owner: ctx.accounts.owner.key(),
+            old_issuer,
+            new_issuer: kyc_issuer,
+        });
+        Ok(())
+    }
+
+    /// Select how the sale-wide price is computed (owner-only, before any token is sold).
+    pub fn set_pricing_mode(ctx: Context<OnlyOwner>, pricing_mode: PricingMode) -> Result<()> {
+        pricing_mode.validate()?;
+
+        let state = &mut ctx.accounts.state;
+        require!(state.token_sold == 0, DcoError::SaleAlreadyStarted);
+        state.pricing_mode = pricing_mode;
+
+        emit!(PricingModeUpdated {
+            owner: ctx.accounts.owner.key(),
+            pricing_mode,
+        });
+        Ok(())
+    }
+
+    /// Read-only quote of the average price a purchase of `amount` zktc base units would settle at right now
+    /// (on a bonding curve the price rises across the purchase; pass 0 for the spot price).
+    /// Pass the active `round` once rounds exist, and the `price_feed` when pegged.
+    /// Returned via transaction return data.
+    pub fn quote_price(ctx: Context<QuotePrice>, amount: u64) -> Result<u128> {
+        let now = Clock::get()?.unix_timestamp;
+        let (token_price, _) =
+            active_round_terms(&ctx.accounts.state, ctx.accounts.round.as_deref(), amount, now)?;
+        pegged_price(
+            &ctx.accounts.state,
+            ctx.accounts.price_feed.as_ref(),
+            token_price,
+            now,
+        )
+    }
+
+    /// Peg the price of a SOL sale to USD through a `PriceFeed` account (owner-only, before any token is sold).
+    /// Once set, `token_price`, round prices and pricing modes are read in micro-USD per whole zktc.
+    /// - `max_price_age`: max seconds since the feed's `publish_time`
+    /// - `max_price_conf_bps`: max confidence interval relative to the price, in basis points
+    ///
+    /// Pass `Pubkey::default()` to remove the peg.
+    pub fn set_price_feed(
+        ctx: Context<OnlyOwner>,
+        price_feed: Pubkey,
+        max_price_age: i64,
+        max_price_conf_bps: u16,
+    ) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        require!(state.token_sold == 0, DcoError::SaleAlreadyStarted);
+        require!(state.is_native_quote(), DcoError::InvalidQuoteMint);
+        if price_feed != Pubkey::default() {
+            require!(max_price_age > 0, DcoError::InvalidPriceFeed);
+            require!(
+                max_price_conf_bps as u64 <= DcoState::BPS_DENOMINATOR,
+                DcoError::InvalidPriceFeed
+            );
+        }
+
+        state.price_feed = price_feed;
+        state.max_price_age = max_price_age;
+        state.max_price_conf_bps = max_price_conf_bps;
+
+        emit!(PriceFeedUpdated {
+            owner: ctx.accounts.owner.key(),
+            price_feed,
+            max_price_age,
+            max_price_conf_bps,
+        });
+        Ok(())
+    }
+
+    /// Set the ZCW donation rate in basis points (owner-only)
+    pub fn set_donation_bps(ctx: Context<OnlyOwner>, donation_bps: u16) -> Result<()> {
+        require!(
+            donation_bps as u64 <= DcoState::BPS_DENOMINATOR,
+            DcoError::InvalidDonationBps
+        );
+
+        let state = &mut ctx.accounts.state;
+        let old_bps = state.donation_bps;
+        state.donation_bps = donation_bps;
+
+        emit!(DonationBpsUpdated {
+            owner: ctx.accounts.owner.key(),
+            old_bps,
+            new_bps: donation_bps,
+        });
+        Ok(())
+    }
+
+    /// Add the next sale round (owner-only). Rounds may not overlap and must end by `dco_end_time`.
+    /// - `price`: token price for this round, same unit as `token_price`
+    /// - `allocation`: max tokens sold in this round
+    /// - `allowlist`: whether buyers in this round must prove an allowlist entry
+    pub fn add_round(
+        ctx: Context<AddRound>,
+        start_time: i64,
+        end_time: i64,
+        price: u128,
+        allocation: u64,
+        allowlist: bool,
+    ) -> Result<()> {
+        require!(price > 0, DcoError::TokenPriceMustBeGreaterThanZero);
+        require!(allocation > 0, DcoError::AmountMustBeGreaterThanZero);
+
+        let state = &mut ctx.accounts.state;
+        require!(
+            start_time < end_time
+                && start_time >= state.last_round_end
+                && end_time <= state.dco_end_time,
+            DcoError::InvalidRoundWindow
+        );
+
+        let index = state.round_count;
+        state.round_count = index.checked_add(1).ok_or(DcoError::MathOverflow)?;
+        state.last_round_end = end_time;
+
+        let round = &mut ctx.accounts.round;
+        round.state = state.key();
+        round.index = index;
+        round.start_time = start_time;
+        round.end_time = end_time;
+        round.price = price;
+        round.allocation = allocation;
+        round.sold = 0;
+        round.allowlist = allowlist;
+        round.bump = ctx.bumps.round;
+
+        emit!(RoundAdded {
+            owner: ctx.accounts.owner.key(),
+            index,
+            start_time,
+            end_time,
+            price,
+            allocation,
+            allowlist,
+        });
+        Ok(())
+    }
+
+    /// Set the allowlist Merkle root (owner-only). An all-zero root disables the allowlist.
+    pub fn set_merkle_root(ctx: Context<OnlyOwner>, merkle_root: [u8; 32]) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        let old_root = state.merkle_root;
+        state.merkle_root = merkle_root;
+
+        emit!(MerkleRootUpdated {
+            owner: ctx.accounts.owner.key(),
+            old_root,
+            new_root: merkle_root,
+        });
+        Ok(())
+    }
+
+    /// Close the sale (owner-only): after `dco_end_time`, once donations are flushed and nothing
+    /// is owed to vesting, referrers or refunds, sweep the vault to the owner, close the vault and the
+    /// state PDA (and the quote treasury, if any), and return the treasury funds and all rent to the owner.
+    /// Fair-launch commitments must be settled first; the owner can crank `claim_commitment` for absent
+    /// buyers, and `claim_refund` after a failed sale. A `DcoTombstone` is left in place of the state so
+    /// the same seed cannot be initialized again over the surviving per-buyer accounts.
+    pub fn finalize(ctx: Context<Finalize>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let state = &ctx.accounts.state;
+        require!(now >= state.dco_end_time, DcoError::DcoNotActive);
+        require!(state.total_donations == 0, DcoError::DonationsNotFlushed);
+        require!(
+            state.total_commit_settled == state.total_committed,
+            DcoError::CommitmentsOutstanding
+        );
+        // vesting is never claimable after a failed sale, only refunds have to be settled
+        if state.sale_failed(now) {
+            require!(
+                state.total_refunded == state.total_raised - state.fair_launch_raised,
+                DcoError::RefundsOutstanding
+            );
+        } else {
+            require!(state.outstanding_vesting() == 0, DcoError::VestingOutstanding);
+            require!(
+                state.total_referral_accrued == state.total_referral_claimed,
+                DcoError::ReferralsOutstanding
+            );
+        }
+
+        let seed_key = state.seed_key;
+        let bump = state.bump;
+        let seeds = &[
+            b"dco_state".as_ref(),
+            seed_key.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        // sweep remaining tokens vault -> owner (PDA signs)
+        let swept = ctx.accounts.vault.amount;
+        if swept > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.vault.to_account_info(),
+                to: ctx.accounts.owner_token_account.to_account_info(),
+                authority: ctx.accounts.state.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, swept)?;
+        }
+
+        // close vault, rent -> owner (PDA signs)
+        let cpi_accounts = CloseAccount {
+            account: ctx.accounts.vault.to_account_info(),
+            destination: ctx.accounts.owner.to_account_info(),
+            authority: ctx.accounts.state.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::close_account(cpi_ctx)?;
+
+        // sweep and close the quote treasury, if any (PDA signs)
+        let mut quote_swept = 0u64;
+        if !ctx.accounts.state.is_native_quote() {
+            let (Some(owner_quote_account), Some(quote_treasury)) = (
+                ctx.accounts.owner_quote_account.as_ref(),
+                ctx.accounts.quote_treasury.as_ref(),
+            ) else {
+                return err!(DcoError::QuoteAccountsRequired);
+            };
+
+            quote_swept = quote_treasury.amount;
+            if quote_swept > 0 {
+                let cpi_accounts = Transfer {
+                    from: quote_treasury.to_account_info(),
+                    to: owner_quote_account.to_account_info(),
+                    authority: ctx.accounts.state.to_account_info(),
+                };
+                let cpi_program = ctx.accounts.token_program.to_account_info();
+                let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+                token::transfer(cpi_ctx, quote_swept)?;
+            }
+
+            let cpi_accounts = CloseAccount {
+                account: quote_treasury.to_account_info(),
+                destination: ctx.accounts.owner.to_account_info(),
+                authority: ctx.accounts.state.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::close_account(cpi_ctx)?;
+        }
+
+        // empty treasury -> owner (treasury PDA signs)
+        let treasury_lamports = ctx.accounts.treasury.lamports();
+        if treasury_lamports > 0 {
+            let state_key = ctx.accounts.state.key();
+            let treasury_bump = ctx.bumps.treasury;
+            let treasury_seeds = &[
+                b"dco_treasury".as_ref(),
+                state_key.as_ref(),
+                &[treasury_bump],
+            ];
+            let treasury_signer = &[&treasury_seeds[..]];
+
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.treasury.to_account_info(),
+                to: ctx.accounts.owner.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, treasury_signer);
+            system_program::transfer(cpi_ctx, treasury_lamports)?;
+        }
+
+        let tombstone = &mut ctx.accounts.tombstone;
+        tombstone.state = ctx.accounts.state.key();
+        tombstone.finalized_at = now;
+        tombstone.bump = ctx.bumps.tombstone;
+
+        // state account itself is closed to the owner by the `close` constraint
+        emit!(Finalized {
+            owner: ctx.accounts.owner.key(),
+            tokens_swept: swept,
+            quote_swept,
+            treasury_lamports,
+        });
+
+        Ok(())
+    }
+
+    /// Add a new releaser, or update an existing releaser's daily quota (owner-only)
+    /// - `daily_limit`: max zktc base units the releaser may release per UTC day
+    pub fn add_releaser(ctx: Context<OnlyOwner>, new_releaser: Pubkey, daily_limit: u64) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+
+        if let Some(quota) = state
+            .releaser_quotas
+            .iter_mut()
+            .find(|q| q.releaser == new_releaser)
+        {
+            quota.daily_limit = daily_limit;
+        } else {
+            require!(
+                state.releasers.len() < DcoState::MAX_RELEASERS,
+                DcoError::ReleasersFull
+            );
+            state.releasers.push(new_releaser);
+            state.releaser_quotas.push(ReleaserQuota {
+                releaser: new_releaser,
+                daily_limit,
+                day: 0,
+                released_today: 0,
+            });
+        }
+
+        emit!(ReleaserAdded {
+            owner: ctx.accounts.owner.key(),
+            new_releaser,
+            daily_limit,
+        });
+        Ok(())
+    }
+
+    /// Remove a releaser (owner-only)
+    pub fn remove_releaser(ctx: Context<OnlyOwner>, old_releaser: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        state.releasers.retain(|r| *r != old_releaser);
+        state.releaser_quotas.retain(|q| q.releaser != old_releaser);
+
+        emit!(ReleaserRemoved {
+            owner: ctx.accounts.owner.key(),
+            old_releaser,
+        });
+        Ok(())
+    }
+
+    /// Replace the donation beneficiary table (owner-only). Weights must sum to 10000 bps;
+    /// an empty table sends all donations to `zcw`.
+    pub fn set_beneficiaries(ctx: Context<OnlyOwner>, beneficiaries: Vec<Beneficiary>) -> Result<()> {
+        require!(
+            beneficiaries.len() <= DcoState::MAX_BENEFICIARIES,
+            DcoError::InvalidBeneficiaries
+        );
+        if !beneficiaries.is_empty() {
+            let total_weight: u64 = beneficiaries.iter().map(|b| b.weight_bps as u64).sum();
+            require!(
+                total_weight == DcoState::BPS_DENOMINATOR,
+                DcoError::InvalidBeneficiaries
+            );
+        }
+
+        let state = &mut ctx.accounts.state;
+        state.beneficiaries = beneficiaries.clone();
+
+        emit!(BeneficiariesUpdated {
+            owner: ctx.accounts.owner.key(),
+            beneficiaries,
+        });
+        Ok(())
+    }
+
+    /// Set the guardian allowed to pause the DCO alongside the owner (owner-only).
+    /// The guardian must be a different key than the owner.
+    pub fn set_guardian(ctx: Context<OnlyOwner>, new_guardian: Pubkey) -> Result<()> {
+        require_keys_neq!(new_guardian, ctx.accounts.state.owner, DcoError::InvalidGuardian);
+        let state = &mut ctx.accounts.state;
+        let old_guardian = state.guardian;
+        state.guardian = new_guardian;
+
+        emit!(GuardianUpdated {
+            owner: ctx.accounts.owner.key(),
+            old_guardian,
+            new_guardian,
+        });
+        Ok(())
+    }
+
+    /// Emergency stop for sales, releases, supply injection and donations (guardian or owner)
+    pub fn pause(ctx: Context<PauseDco>, reason: u8) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        require!(
+            ctx.accounts.caller.key() == state.guardian
+                || ctx.accounts.caller.key() == state.owner,
+            DcoError::Unauthorized
+        );
+        state.paused = true;
+
+        emit!(DcoPaused {
+            caller: ctx.accounts.caller.key(),
+            reason,
+        });
+        Ok(())
+    }
+
+    /// Lift the emergency stop (owner-only)
+    pub fn unpause(ctx: Context<OnlyOwner>, reason: u8) -> Result<()> {
+        ctx.accounts.state.paused = false;
+
+        emit!(DcoUnpaused {
+            owner: ctx.accounts.owner.key(),
+            reason,
+        });
+        Ok(())
+    }
+
+    /// Propose a new owner (owner-only). Takes effect once the new owner calls `accept_owner`.
+    pub fn propose_owner(ctx: Context<OnlyOwner>, new_owner: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        state.pending_owner = new_owner;
+
+        emit!(OwnershipProposed {
+            owner: ctx.accounts.owner.key(),
+            pending_owner: new_owner,
+        });
+        Ok(())
+    }
+
+    /// Accept a pending ownership transfer (pending owner only).
+    /// The state PDA keeps its original seeds, so the address does not change.
+    /// The current guardian cannot become owner; the owner has to set another guardian first.
+    pub fn accept_owner(ctx: Context<AcceptOwner>) -> Result<()> {
+        require_keys_neq!(
+            ctx.accounts.new_owner.key(),
+            ctx.accounts.state.guardian,
+            DcoError::InvalidGuardian
+        );
+        let state = &mut ctx.accounts.state;
+        let old_owner = state.owner;
+        state.owner = ctx.accounts.new_owner.key();
+        state.pending_owner = Pubkey::default();
+
+        emit!(OwnershipTransferred {
+            old_owner,
+            new_owner: state.owner,
+        });
+        Ok(())
+    }
+
+    /// Migrate a `DcoState` created before caps, vesting, rounds and the later sale settings existed
+    /// (owner only): grows it to `DcoState::SPACE`, with the owner paying the extra rent, and fills the
+    /// new fields so the sale keeps behaving as before: no soft cap or hard cap, nothing vested, no
+    /// donation share on sales, and every existing releaser keeps an unlimited daily quota
+    pub fn migrate_state(ctx: Context<MigrateState>) -> Result<()> {
+        let state_info = ctx.accounts.state.to_account_info();
+        require!(
+            state_info.data_len() < DcoState::SPACE,
+            DcoError::AlreadyMigrated
+        );
+        let legacy = {
+            let data = state_info.try_borrow_data()?;
+            require!(
+                data.get(..8) == Some(DcoState::DISCRIMINATOR),
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            LegacyDcoState::deserialize(&mut &data[8..])
+                .map_err(|_| ErrorCode::AccountDidNotDeserialize)?
+        };
+        require_keys_eq!(
+            legacy.zk_token_mint,
+            ctx.accounts.token_mint.key(),
+            DcoError::InvalidTokenMint
+        );
+
+        let lamports = Rent::get()?.minimum_balance(DcoState::SPACE);
+        let top_up = lamports.saturating_sub(state_info.lamports());
+        if top_up > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.owner.to_account_info(),
+                to: state_info.clone(),
+            };
+            let cpi_ctx = CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_ctx, top_up)?;
+        }
+        state_info.resize(DcoState::SPACE)?;
+
+        let releaser_quotas = legacy
+            .releasers
+            .iter()
+            .map(|releaser| ReleaserQuota {
+                releaser: *releaser,
+                daily_limit: u64::MAX,
+                day: 0,
+                released_today: 0,
+            })
+            .collect();
+        let state = DcoState {
+            owner: legacy.owner,
+            zk_token_mint: legacy.zk_token_mint,
+            vault: legacy.vault,
+            token_price: legacy.token_price,
+            token_sold: legacy.token_sold,
+            total_donations: legacy.total_donations,
+            dco_end_time: legacy.dco_end_time,
+            zcw: legacy.zcw,
+            releasers: legacy.releasers,
+            bump: legacy.bump,
+            soft_cap: 0,
+            hard_cap: u64::MAX,
+            total_raised: 0,
+            total_refunded: 0,
+            vesting_cliff: 0,
+            vesting_duration: 0,
+            tge_unlock_bps: 0,
+            total_vested: 0,
+            total_claimed: 0,
+            merkle_root: [0u8; 32],
+            round_count: 0,
+            last_round_end: 0,
+            donation_bps: 0,
+            seed_key: legacy.owner,
+            pending_owner: Pubkey::default(),
+            releaser_quotas,
+            guardian: Pubkey::default(),
+            paused: false,
+            beneficiaries: Vec::new(),
+            pricing_mode: PricingMode::Fixed,
+            referral_bps: 0,
+            total_referral_accrued: 0,
+            total_referral_claimed: 0,
+            kyc_issuer: Pubkey::default(),
+            zktc_decimals: ctx.accounts.token_mint.decimals,
+            quote_mint: Pubkey::default(),
+            quote_decimals: DcoState::NATIVE_SOL_DECIMALS,
+            quote_treasury: Pubkey::default(),
+            price_feed: Pubkey::default(),
+            max_price_age: 0,
+            max_price_conf_bps: 0,
+            fair_launch_supply: 0,
+            total_committed: 0,
+            total_commit_settled: 0,
+            settled: false,
+            settle_price: 0,
+            fair_launch_raised: 0,
+            fair_launch_reserved: 0,
+        };
+        let mut data = state_info.try_borrow_mut_data()?;
+        state.try_serialize(&mut &mut data[..])?;
+
+        emit!(StateMigrated {
+            owner: state.owner,
+            state: state_info.key(),
+        });
+
+        Ok(())
+    }
+}
+
+/// Helper: zktc base units bought with `quote_amount` at `price` quote base units per whole zktc.
+/// Rounds down, in the project's favour.
+pub fn tokens_for_quote(quote_amount: u64, price: u128, zktc_decimals: u8) -> Result<u64> {
+    require!(price > 0, DcoError::TokenPriceMustBeGreaterThanZero);
+    let one_token = 10u128
+        .checked_pow(zktc_decimals as u32)
+        .ok_or(DcoError::MathOverflow)?;
+    let tokens = (quote_amount as u128)
+        .checked_mul(one_token)
+        .ok_or(DcoError::MathOverflow)?
+        / price;
+    Ok(u64::try_from(tokens).map_err(|_| DcoError::AmountTooLarge)?)
+}
+
+/// Helper: quote base units owed for `tokens` zktc base units at `price` quote base units per whole zktc.
+/// Rounds up, in the project's favour.
+pub fn quote_for_tokens(tokens: u64, price: u128, zktc_decimals: u8) -> Result<u64> {
+    let one_token = 10u128
+        .checked_pow(zktc_decimals as u32)
+        .ok_or(DcoError::MathOverflow)?;
+    let quote = (tokens as u128)
+        .checked_mul(price)
+        .ok_or(DcoError::MathOverflow)?
+        .div_ceil(one_token);
+    Ok(u64::try_from(quote).map_err(|_| DcoError::AmountTooLarge)?)
+}
+
+/// Helper: most zktc base units `quote_amount` buys when `price_at(amount)` is the average price of
+/// buying `amount` (non-decreasing in `amount`), and the price they are charged at.
+/// Binary search below the spot-price fill, so `quote_for_tokens(tokens, price) <= quote_amount`.
+pub fn size_purchase(
+    quote_amount: u64,
+    zktc_decimals: u8,
+    price_at: impl Fn(u64) -> Result<u128>,
+) -> Result<(u64, u128)> {
+    let mut low = 0u64;
+    let mut high = tokens_for_quote(quote_amount, price_at(0)?, zktc_decimals)?;
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        if quote_for_tokens(mid, price_at(mid)?, zktc_decimals)? <= quote_amount {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Ok((low, price_at(low)?))
+}
+
+/// Helper: average bonding-curve price of buying `amount` after `sold`, i.e. the integral of
+/// `base_price + slope * x / slope_step` over `[sold, sold + amount]` divided by `amount`:
+/// `base_price + slope * (sold + amount / 2) / slope_step`, rounded up.
+pub fn bonding_curve_price(
+    base_price: u128,
+    slope: u128,
+    slope_step: u64,
+    sold: u128,
+    amount: u64,
+) -> Result<u128> {
+    let midpoint_x2 = sold
+        .checked_mul(2)
+        .and_then(|s| s.checked_add(amount as u128))
+        .ok_or(DcoError::MathOverflow)?;
+    let increase = slope
+        .checked_mul(midpoint_x2)
+        .ok_or(DcoError::MathOverflow)?
+        .div_ceil(2 * slope_step as u128);
+    Ok(base_price
+        .checked_add(increase)
+        .ok_or(DcoError::MathOverflow)?)
+}
+
+/// Helper: fair-launch fill for `committed` out of `total_committed` quote base units.
+/// tokens = min(tokens_for_quote(committed), committed * supply / total_committed), both rounded down,
+/// cost = quote_for_tokens(tokens), rounded up. Fills never add up to more than `supply`
+/// and `cost <= committed`, so the refund `committed - cost` cannot underflow.
+pub fn fair_launch_allocation(
+    committed: u64,
+    total_committed: u64,
+    supply: u64,
+    price: u128,
+    zktc_decimals: u8,
+) -> Result<(u64, u64)> {
+    require!(total_committed > 0, DcoError::NothingCommitted);
+    let pro_rata = (committed as u128)
+        .checked_mul(supply as u128)
+        .ok_or(DcoError::MathOverflow)?
+        / total_committed as u128;
+    // pro_rata <= supply since committed <= total_committed
+    let tokens = tokens_for_quote(committed, price, zktc_decimals)?.min(pro_rata as u64);
+    let cost = quote_for_tokens(tokens, price, zktc_decimals)?;
+    Ok((tokens, cost))
+}
+
+/// Helper: donation share of a purchase, in zktc base units
+fn donation_share(amount: u64, donation_bps: u16) -> Result<u64> {
+    let donation = (amount as u128)
+        .checked_mul(donation_bps as u128)
+        .ok_or(DcoError::MathOverflow)?
+        / DcoState::BPS_DENOMINATOR as u128;
+    Ok(u64::try_from(donation).map_err(|_| DcoError::AmountTooLarge)?)
+}
+
+/// Helper: referral bonus on a referred amount, in zktc base units
+fn referral_share(amount: u64, referral_bps: u16) -> Result<u64> {
+    let bonus = (amount as u128)
+        .checked_mul(referral_bps as u128)
+        .ok_or(DcoError::MathOverflow)?
+        / DcoState::BPS_DENOMINATOR as u128;
+    Ok(u64::try_from(bonus).map_err(|_| DcoError::AmountTooLarge)?)
+}
+
+/// Helper: accrue `bonus` to the referrer and the state totals
+fn credit_referral(state: &mut DcoState, referral: &mut ReferralAccount, bonus: u64) -> Result<()> {
+    referral.accrued = referral
+        .accrued
+        .checked_add(bonus)
+        .ok_or(DcoError::MathOverflow)?;
+    state.total_referral_accrued = state
+        .total_referral_accrued
+        .checked_add(bonus as u128)
+        .ok_or(DcoError::MathOverflow)?;
+
+    emit!(ReferralCredited {
+        referrer: referral.referrer,
+        bonus,
+        accrued: referral.accrued,
+    });
+    Ok(())
+}
+
+/// Helper: when a KYC issuer is set, require the buyer to hold a frozen (soulbound)
+/// credential token whose metadata comes from that issuer
+fn check_kyc(
+    kyc_issuer: Pubkey,
+    buyer: Pubkey,
+    kyc_token_account: Option<&TokenAccount>,
+    kyc_metadata: Option<&Metadata>,
+) -> Result<()> {
+    if kyc_issuer == Pubkey::default() {
+        return Ok(());
+    }
+
+    let token_account = kyc_token_account.ok_or(DcoError::KycRequired)?;
+    let metadata = kyc_metadata.ok_or(DcoError::KycRequired)?;
+
+    require!(
+        token_account.owner == buyer && token_account.amount == 1 && token_account.is_frozen(),
+        DcoError::InvalidKycCredential
+    );
+    require!(
+        metadata.mint == token_account.mint,
+        DcoError::InvalidKycCredential
+    );
+
+    // only verified fields prove the issuer signed off; anyone can name it update authority
+    let from_issuer = metadata
+        .collection
+        .as_ref()
+        .is_some_and(|c| c.verified && c.key == kyc_issuer)
+        || metadata
+            .creators
+            .as_ref()
+            .is_some_and(|creators| {
+                creators
+                    .iter()
+                    .any(|c| c.verified && c.address == kyc_issuer)
+            });
+    require!(from_issuer, DcoError::InvalidKycCredential);
+
+    Ok(())
+}
+
+/// Helper: resolve the price of buying `amount` zktc base units and the allowlist flag from the active round.
+/// Without rounds the sale-wide `pricing_mode` applies and the allowlist is enforced if set.
+fn active_round_terms(
+    state: &DcoState,
+    round: Option<&Round>,
+    amount: u64,
+    now: i64,
+) -> Result<(u128, bool)> {
+    match round {
+        Some(round) => {
+            require!(
+                now >= round.start_time && now < round.end_time,
+                DcoError::RoundNotActive
+            );
+            Ok((round.price, round.allowlist))
+        }
+        None => {
+            require!(state.round_count == 0, DcoError::RoundRequired);
+            Ok((state.purchase_price(amount, now)?, true))
+        }
+    }
+}
+
+/// Helper: convert a micro-USD price into lamports per whole zktc when `price_feed` is set.
+/// Rejects stale or low-confidence feeds; rounds up, in the project's favour.
+fn pegged_price(
+    state: &DcoState,
+    price_feed: Option<&UncheckedAccount>,
+    usd_price: u128,
+    now: i64,
+) -> Result<u128> {
+    if state.price_feed == Pubkey::default() {
+        return Ok(usd_price);
+    }
+    let Some(price_feed) = price_feed else {
+        return err!(DcoError::PriceFeedRequired);
+    };
+    let feed = PriceFeed::parse(&price_feed.try_borrow_data()?)?;
+
+    require!(
+        now.saturating_sub(feed.publish_time) <= state.max_price_age,
+        DcoError::StalePriceFeed
+    );
+    require!(feed.price > 0, DcoError::InvalidPriceFeed);
+    let sol_price = feed.price as u128;
+    require!(
+        (feed.conf as u128)
+            .checked_mul(DcoState::BPS_DENOMINATOR as u128)
+            .ok_or(DcoError::MathOverflow)?
+            <= sol_price
+                .checked_mul(state.max_price_conf_bps as u128)
+                .ok_or(DcoError::MathOverflow)?,
+        DcoError::PriceConfidenceTooWide
+    );
+
+    // lamports = usd_price * 10^9 / (sol_price * 10^(expo + 6))
+    let scale = feed
+        .expo
+        .checked_add(DcoState::USD_PRICE_DECIMALS as i32)
+        .ok_or(DcoError::MathOverflow)?;
+    let mut numerator = usd_price
+        .checked_mul(10u128.pow(DcoState::NATIVE_SOL_DECIMALS as u32))
+        .ok_or(DcoError::MathOverflow)?;
+    let mut denominator = sol_price;
+    let factor = 10u128
+        .checked_pow(scale.unsigned_abs())
+        .ok_or(DcoError::MathOverflow)?;
+    if scale >= 0 {
+        denominator = denominator
+            .checked_mul(factor)
+            .ok_or(DcoError::MathOverflow)?;
+    } else {
+        numerator = numerator.checked_mul(factor).ok_or(DcoError::MathOverflow)?;
+    }
+    Ok(numerator.div_ceil(denominator))
+}
+
+/// Helper: enforce the allowlist (if any) and the wallet's allocation cap
+fn check_allowlist(
+    state: &DcoState,
+    contribution: &Contribution,
+    wallet: Pubkey,
+    amount: u64,
+    max_allocation: u64,
+    proof: &[[u8; 32]],
+    allowlist_required: bool,
+) -> Result<()> {
+    if !allowlist_required || state.merkle_root == [0u8; 32] {
+        return Ok(());
+    }
+
+    require!(
+        verify_allocation(&state.merkle_root, &wallet, max_allocation, proof),
+        DcoError::InvalidMerkleProof
+    );
+
+    let allocated = contribution
+        .tokens_purchased
+        .checked_add(contribution.tokens_released)
+        .and_then(|a| a.checked_add(amount))
+        .ok_or(DcoError::MathOverflow)?;
+    require!(allocated <= max_allocation, DcoError::AllocationExceeded);
+
+    Ok(())
+}
+
+/// Helper: verify a Merkle proof for the leaf `hash(wallet || max_allocation_le)`.
+/// Inner nodes hash the sorted pair of children, so proofs carry no left/right flags.
+fn verify_allocation(
+    root: &[u8; 32],
+    wallet: &Pubkey,
+    max_allocation: u64,
+    proof: &[[u8; 32]],
+) -> bool {
+    let mut node = hashv(&[wallet.as_ref(), &max_allocation.to_le_bytes()]).to_bytes();
+    for sibling in proof {
+        node = if node <= *sibling {
+            hashv(&[&node, sibling]).to_bytes()
+        } else {
+            hashv(&[sibling, &node]).to_bytes()
+        };
+    }
+    node == *root
+}
+
+//
+// --- Accounts / State types ---
+//
+
+#[derive(Accounts)]
+#[instruction(token_price: u128, dco_end_time: i64, zcw: Pubkey, soft_cap: u64, hard_cap: u64)]
+pub struct Initialize<'info> {
+    /// Owner who creates the DCO and pays for account creation
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// The zktc token mint (must be an existing Mint)
+    pub token_mint: Account<'info, Mint>,
 
     /// DCO state PDA: seeds = [b"dco_state", owner.key().as_ref()]
+    /// The creating owner is stored as `seed_key` so ownership can move without changing the PDA.
+    #[account(
+        init,
+        payer = owner,
+        space = DcoState::SPACE,
+        seeds = [b"dco_state", owner.key().as_ref()],
+        bump
+    )]
+    pub state: Account<'info, DcoState>,
+
+    /// Vault token account for holding zktc tokens.
+    /// seeds = [b"dco_vault", state.key().as_ref()]
+    /// token::authority = state (PDA)
+    #[account(
+        init,
+        payer = owner,
+        token::mint = token_mint,
+        token::authority = state,
+        seeds = [b"dco_vault", state.key().as_ref()],
+        bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    /// Tombstone of a finalized sale under this state PDA, must not exist.
+    /// seeds = [b"dco_closed", state.key().as_ref()]
+    /// CHECK: only its owner is read
+    #[account(
+        seeds = [b"dco_closed", state.key().as_ref()],
+        bump,
+        constraint = *tombstone.owner != crate::ID @ DcoError::SaleFinalized
+    )]
+    pub tombstone: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct InjectSupply<'info> {
+    #[account(mut, has_one = owner)]
+    pub state: Account<'info, DcoState>,
+
+    #[account(
+        mut,
+        constraint = from.mint == state.zk_token_mint @ DcoError::InvalidTokenMint,
+        constraint = from.owner == owner.key() @ DcoError::Unauthorized
+    )]
+    pub from: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
+    pub vault: Account<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment_ref: [u8; 32])]
+pub struct ReleaseZktc<'info> {
+    #[account(mut, has_one = vault)]
+    pub state: Account<'info, DcoState>,
+
+    /// Caller (owner or releaser), pays for the vesting account on first release
+    #[account(mut)]
+    pub caller: Signer<'info>,
+
+    /// CHECK: buyer wallet, only used as the vesting PDA seed
+    pub buyer: UncheckedAccount<'info>,
+
+    /// Buyer's vesting account.
+    /// seeds = [b"vesting", state.key().as_ref(), buyer.key().as_ref()]
+    #[account(
+        init_if_needed,
+        payer = caller,
+        space = VestingAccount::SPACE,
+        seeds = [b"vesting", state.key().as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub vesting: Account<'info, VestingAccount>,
+
+    /// Buyer's contribution record, tracks released tokens against the allowlist cap.
+    /// seeds = [b"contribution", state.key().as_ref(), buyer.key().as_ref()]
+    #[account(
+        init_if_needed,
+        payer = caller,
+        space = Contribution::SPACE,
+        seeds = [b"contribution", state.key().as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub contribution: Account<'info, Contribution>,
+
+    /// Release receipt for the off-chain payment.
+    /// seeds = [b"receipt", state.key().as_ref(), payment_ref.as_ref()]
+    #[account(
+        init_if_needed,
+        payer = caller,
+        space = ReleaseReceipt::SPACE,
+        seeds = [b"receipt", state.key().as_ref(), payment_ref.as_ref()],
+        bump
+    )]
+    pub receipt: Account<'info, ReleaseReceipt>,
+
+    /// Active sale round, required once any round exists
+    #[account(mut, has_one = state)]
+    pub round: Option<Account<'info, Round>>,
+
+    /// Referrer's referral account, if the buyer was referred
+    #[account(mut, has_one = state)]
+    pub referral: Option<Account<'info, ReferralAccount>>,
+
+    /// Buyer's soulbound KYC credential token account, required when `kyc_issuer` is set
+    pub kyc_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// Token metadata of the KYC credential mint
+    pub kyc_metadata: Option<Account<'info, MetadataAccount>>,
+
+    /// Vault token account (PDA authority)
+    #[account(constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
+    pub vault: Account<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Buy<'info> {
+    #[account(mut, has_one = vault)]
+    pub state: Account<'info, DcoState>,
+
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    /// Treasury PDA collecting SOL payments.
+    /// seeds = [b"dco_treasury", state.key().as_ref()]
+    #[account(
+        mut,
+        seeds = [b"dco_treasury", state.key().as_ref()],
+        bump
+    )]
+    pub treasury: SystemAccount<'info>,
+
+    /// Per-buyer contribution record.
+    /// seeds = [b"contribution", state.key().as_ref(), buyer.key().as_ref()]
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = Contribution::SPACE,
+        seeds = [b"contribution", state.key().as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub contribution: Account<'info, Contribution>,
+
+    /// Buyer's vesting account.
+    /// seeds = [b"vesting", state.key().as_ref(), buyer.key().as_ref()]
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = VestingAccount::SPACE,
+        seeds = [b"vesting", state.key().as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub vesting: Account<'info, VestingAccount>,
+
+    /// Active sale round, required once any round exists
+    #[account(mut, has_one = state)]
+    pub round: Option<Account<'info, Round>>,
+
+    /// Referrer's referral account, if the buyer was referred
+    #[account(mut, has_one = state)]
+    pub referral: Option<Account<'info, ReferralAccount>>,
+
+    /// Buyer's soulbound KYC credential token account, required when `kyc_issuer` is set
+    pub kyc_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// Token metadata of the KYC credential mint
+    pub kyc_metadata: Option<Account<'info, MetadataAccount>>,
+
+    /// Buyer's quote mint token account, required once a quote mint is set
+    #[account(
+        mut,
+        constraint = buyer_quote_account.mint == state.quote_mint @ DcoError::InvalidQuoteMint,
+        constraint = buyer_quote_account.owner == buyer.key() @ DcoError::Unauthorized
+    )]
+    pub buyer_quote_account: Option<Account<'info, TokenAccount>>,
+
+    /// Quote treasury token account, required once a quote mint is set
+    #[account(mut, address = state.quote_treasury)]
+    pub quote_treasury: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: `PriceFeed` layout parsed in `pegged_price`, required once a price feed is set
+    #[account(address = state.price_feed)]
+    pub price_feed: Option<UncheckedAccount<'info>>,
+
+    /// Vault token account (PDA authority)
+    #[account(constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
+    pub vault: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRefund<'info> {
+    #[account(mut)]
+    pub state: Account<'info, DcoState>,
+
+    /// The buyer, or the owner cranking the refund of an absent buyer
+    #[account(
+        constraint = caller.key() == buyer.key() || caller.key() == state.owner @ DcoError::Unauthorized
+    )]
+    pub caller: Signer<'info>,
+
+    /// Buyer who made the contribution, receives the refund
+    #[account(mut)]
+    pub buyer: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"dco_treasury", state.key().as_ref()],
+        bump
+    )]
+    pub treasury: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"contribution", state.key().as_ref(), buyer.key().as_ref()],
+        bump = contribution.bump
+    )]
+    pub contribution: Account<'info, Contribution>,
+
+    #[account(
+        mut,
+        seeds = [b"vesting", state.key().as_ref(), buyer.key().as_ref()],
+        bump = vesting.bump
+    )]
+    pub vesting: Account<'info, VestingAccount>,
+
+    /// Buyer's quote mint token account, required once a quote mint is set
+    #[account(
+        mut,
+        constraint = buyer_quote_account.mint == state.quote_mint @ DcoError::InvalidQuoteMint,
+        constraint = buyer_quote_account.owner == buyer.key() @ DcoError::Unauthorized
+    )]
+    pub buyer_quote_account: Option<Account<'info, TokenAccount>>,
+
+    /// Quote treasury token account, required once a quote mint is set
+    #[account(mut, address = state.quote_treasury)]
+    pub quote_treasury: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimVested<'info> {
+    #[account(mut, has_one = vault)]
+    pub state: Account<'info, DcoState>,
+
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vesting", state.key().as_ref(), buyer.key().as_ref()],
+        bump = vesting.bump
+    )]
+    pub vesting: Account<'info, VestingAccount>,
+
+    #[account(mut, constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
+    pub vault: Account<'info, TokenAccount>,
+
+    /// Buyer's token account (must be token account for same mint)
+    #[account(
+        mut,
+        constraint = buyer_token_account.mint == state.zk_token_mint @ DcoError::InvalidTokenMint,
+        constraint = buyer_token_account.owner == buyer.key() @ DcoError::Unauthorized
+    )]
+    pub buyer_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct DonateToZcw<'info> {
+    #[account(mut, has_one = vault)]
+    pub state: Account<'info, DcoState>,
+
+    pub caller: Signer<'info>,
+
+    #[account(mut, constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
+    pub vault: Account<'info, TokenAccount>,
+
+    /// zcw token account (destination when no beneficiary table is set)
+    #[account(
+        mut,
+        constraint = zcw_token_account.mint == state.zk_token_mint @ DcoError::InvalidTokenMint
+    )]
+    pub zcw_token_account: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct AddRound<'info> {
+    #[account(mut, has_one = owner)]
+    pub state: Account<'info, DcoState>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// Round PDA: seeds = [b"dco_round", state.key().as_ref(), &[state.round_count]]
+    #[account(
+        init,
+        payer = owner,
+        space = Round::SPACE,
+        seeds = [b"dco_round", state.key().as_ref(), &[state.round_count]],
+        bump
+    )]
+    pub round: Account<'info, Round>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateState<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// CHECK: `DcoState` in the `LegacyDcoState` layout, checked and rewritten in migrate_state
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"dco_state", owner.key().as_ref()],
+        bump
+    )]
+    pub state: UncheckedAccount<'info>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OnlyOwner<'info> {
+    #[account(mut, has_one = owner)]
+    pub state: Account<'info, DcoState>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    pub state: Account<'info, DcoState>,
+
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    /// Referral PDA: seeds = [b"referral", state.key().as_ref(), referrer.key().as_ref()]
+    #[account(
+        init,
+        payer = referrer,
+        space = ReferralAccount::SPACE,
+        seeds = [b"referral", state.key().as_ref(), referrer.key().as_ref()],
+        bump
+    )]
+    pub referral: Account<'info, ReferralAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferral<'info> {
+    #[account(mut, has_one = vault)]
+    pub state: Account<'info, DcoState>,
+
+    pub referrer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"referral", state.key().as_ref(), referrer.key().as_ref()],
+        bump = referral.bump
+    )]
+    pub referral: Account<'info, ReferralAccount>,
+
+    #[account(mut, constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
+    pub vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = referrer_token_account.mint == state.zk_token_mint @ DcoError::InvalidTokenMint,
+        constraint = referrer_token_account.owner == referrer.key() @ DcoError::Unauthorized
+    )]
+    pub referrer_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct QuotePrice<'info> {
+    pub state: Account<'info, DcoState>,
+
+    /// Active sale round, required once any round exists
+    #[account(has_one = state)]
+    pub round: Option<Account<'info, Round>>,
+
+    /// CHECK: `PriceFeed` layout parsed in `pegged_price`, required once a price feed is set
+    #[account(address = state.price_feed)]
+    pub price_feed: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct Finalize<'info> {
+    #[account(mut, has_one = owner, has_one = vault, close = owner)]
+    pub state: Account<'info, DcoState>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(mut, constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
+    pub vault: Account<'info, TokenAccount>,
+
     #[account(
-        init,
-        payer = owner,
-        space = DcoState::SPACE,
-        seeds = [b"dco_state", owner.key().as_ref()],
+        mut,
+        constraint = owner_token_account.mint == state.zk_token_mint @ DcoError::InvalidTokenMint,
+        constraint = owner_token_account.owner == owner.key() @ DcoError::Unauthorized
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"dco_treasury", state.key().as_ref()],
         bump
     )]
-    pub state: Account<'info, DcoState>,
+    pub treasury: SystemAccount<'info>,
 
-    /// Vault token account for holding zktc tokens.
-    /// seeds = [b"dco_vault", state.key().as_ref()]
-    /// token::authority = state (PDA)
+    /// Outlives the state PDA so `initialize` cannot reopen it over the per-buyer accounts.
+    /// seeds = [b"dco_closed", state.key().as_ref()]
     #[account(
         init,
         payer = owner,
-        token::mint = token_mint,
-        token::authority = state,
-        seeds = [b"dco_vault", state.key().as_ref()],
+        space = DcoTombstone::SPACE,
+        seeds = [b"dco_closed", state.key().as_ref()],
         bump
     )]
-    pub vault: Account<'info, TokenAccount>,
+    pub tombstone: Account<'info, DcoTombstone>,
+
+    /// Owner's quote mint token account, required once a quote mint is set
+    #[account(
+        mut,
+        constraint = owner_quote_account.mint == state.quote_mint @ DcoError::InvalidQuoteMint,
+        constraint = owner_quote_account.owner == owner.key() @ DcoError::Unauthorized
+    )]
+    pub owner_quote_account: Option<Account<'info, TokenAccount>>,
+
+    /// Quote treasury token account, required once a quote mint is set
+    #[account(mut, address = state.quote_treasury)]
+    pub quote_treasury: Option<Account<'info, TokenAccount>>,
 
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct InjectSupply<'info> {
-    #[account(mut, has_one = owner)]
+pub struct PauseDco<'info> {
+    #[account(mut)]
+    pub state: Account<'info, DcoState>,
+    /// Guardian or owner
+    pub caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptOwner<'info> {
+    #[account(
+        mut,
+        constraint = state.pending_owner == new_owner.key() @ DcoError::Unauthorized
+    )]
     pub state: Account<'info, DcoState>,
+    pub new_owner: Signer<'info>,
+}
 
+#[derive(Accounts)]
+pub struct OnlyOwnerAfterEnd<'info> {
     #[account(
         mut,
-        constraint = from.mint == state.zk_token_mint @ DcoError::InvalidTokenMint,
-        constraint = from.owner == owner.key() @ DcoError::Unauthorized
+        has_one = owner,
+        constraint = Clock::get()?.unix_timestamp >= state.dco_end_time @ DcoError::DcoNotActive
     )]
-    pub from: Account<'info, TokenAccount>,
+    pub state: Account<'info, DcoState>,
+
+    pub owner: Signer<'info>,
 
     #[account(mut, constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
     pub vault: Account<'info, TokenAccount>,
 
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == state.zk_token_mint @ DcoError::InvalidTokenMint,
+        constraint = owner_token_account.owner == owner.key() @ DcoError::Unauthorized
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(has_one = owner)]
+    pub state: Account<'info, DcoState>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"dco_treasury", state.key().as_ref()],
+        bump
+    )]
+    pub treasury: SystemAccount<'info>,
+
+    /// Owner's quote mint token account, required once a quote mint is set
+    #[account(
+        mut,
+        constraint = owner_quote_account.mint == state.quote_mint @ DcoError::InvalidQuoteMint,
+        constraint = owner_quote_account.owner == owner.key() @ DcoError::Unauthorized
+    )]
+    pub owner_quote_account: Option<Account<'info, TokenAccount>>,
+
+    /// Quote treasury token account, required once a quote mint is set
+    #[account(mut, address = state.quote_treasury)]
+    pub quote_treasury: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetQuoteMint<'info> {
+    #[account(mut, has_one = owner)]
+    pub state: Account<'info, DcoState>,
+
+    #[account(mut)]
     pub owner: Signer<'info>,
 
+    /// SPL mint buyers pay with
+    pub quote_mint: Account<'info, Mint>,
+
+    /// Quote treasury token account.
+    /// seeds = [b"dco_quote_treasury", state.key().as_ref()]
+    /// token::authority = state (PDA)
+    #[account(
+        init,
+        payer = owner,
+        token::mint = quote_mint,
+        token::authority = state,
+        seeds = [b"dco_quote_treasury", state.key().as_ref()],
+        bump
+    )]
+    pub quote_treasury: Account<'info, TokenAccount>,
+
     pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct ReleaseZktc<'info> {
-    #[account(mut, has_one = vault)]
+pub struct Commit<'info> {
+    #[account(mut)]
     pub state: Account<'info, DcoState>,
 
-    /// Caller (owner or releaser)
-    pub caller: Signer<'info>,
+    #[account(mut)]
+    pub buyer: Signer<'info>,
 
-    /// Vault token account (PDA authority)
-    #[account(mut, constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
-    pub vault: Account<'info, TokenAccount>,
+    /// Per-buyer fair-launch commitment.
+    /// seeds = [b"commitment", state.key().as_ref(), buyer.key().as_ref()]
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = Commitment::SPACE,
+        seeds = [b"commitment", state.key().as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub commitment: Account<'info, Commitment>,
 
-    /// Buyer's token account (must be token account for same mint)
+    /// Referrer's referral account, if the buyer was referred
+    #[account(has_one = state)]
+    pub referral: Option<Account<'info, ReferralAccount>>,
+
+    /// Buyer's soulbound KYC credential token account, required when `kyc_issuer` is set
+    pub kyc_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// Token metadata of the KYC credential mint
+    pub kyc_metadata: Option<Account<'info, MetadataAccount>>,
+
+    /// Treasury PDA collecting SOL payments.
     #[account(
         mut,
-        constraint = buyer_token_account.mint == state.zk_token_mint @ DcoError::InvalidTokenMint
+        seeds = [b"dco_treasury", state.key().as_ref()],
+        bump
     )]
-    pub buyer_token_account: Account<'info, TokenAccount>,
+    pub treasury: SystemAccount<'info>,
+
+    /// Buyer's quote mint token account, required once a quote mint is set
+    #[account(
+        mut,
+        constraint = buyer_quote_account.mint == state.quote_mint @ DcoError::InvalidQuoteMint,
+        constraint = buyer_quote_account.owner == buyer.key() @ DcoError::Unauthorized
+    )]
+    pub buyer_quote_account: Option<Account<'info, TokenAccount>>,
+
+    /// Quote treasury token account, required once a quote mint is set
+    #[account(mut, address = state.quote_treasury)]
+    pub quote_treasury: Option<Account<'info, TokenAccount>>,
 
     pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct DonateToZcw<'info> {
+pub struct Settle<'info> {
     #[account(mut, has_one = vault)]
     pub state: Account<'info, DcoState>,
 
     pub caller: Signer<'info>,
 
-    #[account(mut, constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
+    #[account(constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
     pub vault: Account<'info, TokenAccount>,
 
-    /// zcw token account (destination)
+    /// CHECK: `PriceFeed` layout parsed in `pegged_price`, required once a price feed is set
+    #[account(address = state.price_feed)]
+    pub price_feed: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimCommitment<'info> {
+    #[account(mut)]
+    pub state: Account<'info, DcoState>,
+
+    /// Anyone may settle a commitment; pays for the vesting account if it does not exist yet
+    #[account(mut)]
+    pub caller: Signer<'info>,
+
+    /// Buyer who made the commitment, receives the SOL refund
+    #[account(mut)]
+    pub buyer: SystemAccount<'info>,
+
     #[account(
         mut,
-        constraint = zcw_token_account.mint == state.zk_token_mint @ DcoError::InvalidTokenMint
+        seeds = [b"commitment", state.key().as_ref(), buyer.key().as_ref()],
+        bump = commitment.bump
+    )]
+    pub commitment: Account<'info, Commitment>,
+
+    /// Referral account recorded on the commitment, required when it has one
+    #[account(mut, has_one = state)]
+    pub referral: Option<Account<'info, ReferralAccount>>,
+
+    /// Buyer's vesting account, credited with the fill.
+    /// seeds = [b"vesting", state.key().as_ref(), buyer.key().as_ref()]
+    #[account(
+        init_if_needed,
+        payer = caller,
+        space = VestingAccount::SPACE,
+        seeds = [b"vesting", state.key().as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub vesting: Account<'info, VestingAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"dco_treasury", state.key().as_ref()],
+        bump
+    )]
+    pub treasury: SystemAccount<'info>,
+
+    /// Buyer's quote mint token account, required once a quote mint is set
+    #[account(
+        mut,
+        constraint = buyer_quote_account.mint == state.quote_mint @ DcoError::InvalidQuoteMint,
+        constraint = buyer_quote_account.owner == buyer.key() @ DcoError::Unauthorized
     )]
-    pub zcw_token_account: Account<'info, TokenAccount>,
+    pub buyer_quote_account: Option<Account<'info, TokenAccount>>,
+
+    /// Quote treasury token account, required once a quote mint is set
+    #[account(mut, address = state.quote_treasury)]
+    pub quote_treasury: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+/// On-chain state
+#[account]
+pub struct DcoState {
+    pub owner: Pubkey,
+    pub zk_token_mint: Pubkey,
+    pub vault: Pubkey,
+    pub token_price: u128,
+    pub token_sold: u128,
+    pub total_donations: u128,
+    pub dco_end_time: i64,
+    pub zcw: Pubkey,
+    pub releasers: Vec<Pubkey>,
+    pub bump: u8,
+    pub soft_cap: u64,
+    pub hard_cap: u64,
+    pub total_raised: u64,
+    pub total_refunded: u64,
+    pub vesting_cliff: i64,
+    pub vesting_duration: i64,
+    pub tge_unlock_bps: u16,
+    pub total_vested: u128,
+    pub total_claimed: u128,
+    pub merkle_root: [u8; 32],
+    pub round_count: u8,
+    pub last_round_end: i64,
+    pub donation_bps: u16,
+    pub seed_key: Pubkey,
+    pub pending_owner: Pubkey,
+    pub releaser_quotas: Vec<ReleaserQuota>,
+    pub guardian: Pubkey,
+    pub paused: bool,
+    pub beneficiaries: Vec<Beneficiary>,
+    pub pricing_mode: PricingMode,
+    pub referral_bps: u16,
+    pub total_referral_accrued: u128,
+    pub total_referral_claimed: u128,
+    pub kyc_issuer: Pubkey,
+    pub zktc_decimals: u8,
+    pub quote_mint: Pubkey,
+    pub quote_decimals: u8,
+    pub quote_treasury: Pubkey,
+    pub price_feed: Pubkey,
+    pub max_price_age: i64,
+    pub max_price_conf_bps: u16,
+    pub fair_launch_supply: u64,
+    pub total_committed: u64,
+    pub total_commit_settled: u64,
+    pub settled: bool,
+    pub settle_price: u128,
+    pub fair_launch_raised: u64,
+    pub fair_launch_reserved: u64,
+}
+
+impl DcoState {
+    pub const MAX_RELEASERS: usize = 20;
+    pub const MAX_BENEFICIARIES: usize = 10;
+    pub const BPS_DENOMINATOR: u64 = 10_000;
+    // max forward move of dco_end_time per extend_end_time call (30 days)
+    pub const MAX_END_TIME_EXTENSION: i64 = 30 * 24 * 60 * 60;
+    // max token_price change per set_token_price call once sales started (10%)
+    pub const MAX_PRICE_DELTA_BPS: u64 = 1_000;
+    // quote_decimals while payments are in native SOL (lamports)
+    pub const NATIVE_SOL_DECIMALS: u8 = 9;
+    // USD prices are in micro-USD while a price feed is set
+    pub const USD_PRICE_DECIMALS: u8 = 6;
+    // computed size for the fields above
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // owner
+        32 + // zk_token_mint
+        32 + // vault
+        16 + // token_price (u128)
+        16 + // token_sold (u128)
+        16 + // total_donations (u128)
+        8  + // dco_end_time (i64)
+        32 + // zcw
+        4  + (32 * Self::MAX_RELEASERS) + // releasers vec (len + entries)
+        1  + // bump
+        8  + // soft_cap
+        8  + // hard_cap
+        8  + // total_raised
+        8  + // total_refunded
+        8  + // vesting_cliff
+        8  + // vesting_duration
+        2  + // tge_unlock_bps
+        16 + // total_vested (u128)
+        16 + // total_claimed (u128)
+        32 + // merkle_root
+        1  + // round_count
+        8  + // last_round_end
+        2  + // donation_bps
+        32 + // seed_key
+        32 + // pending_owner
+        4  + (ReleaserQuota::SPACE * Self::MAX_RELEASERS) + // releaser_quotas vec (len + entries)
+        32 + // guardian
+        1  + // paused
+        4  + (Beneficiary::SPACE * Self::MAX_BENEFICIARIES) + // beneficiaries vec (len + entries)
+        PricingMode::SPACE + // pricing_mode
+        2  + // referral_bps
+        16 + // total_referral_accrued (u128)
+        16 + // total_referral_claimed (u128)
+        32 + // kyc_issuer
+        1  + // zktc_decimals
+        32 + // quote_mint
+        1  + // quote_decimals
+        32 + // quote_treasury
+        32 + // price_feed
+        8  + // max_price_age
+        2  + // max_price_conf_bps
+        8  + // fair_launch_supply
+        8  + // total_committed
+        8  + // total_commit_settled
+        1  + // settled
+        16 + // settle_price (u128)
+        8  + // fair_launch_raised
+        8;   // fair_launch_reserved
+
+    /// True while payments are taken in native SOL (no quote mint set).
+    pub fn is_native_quote(&self) -> bool {
+        self.quote_mint == Pubkey::default()
+    }
+
+    /// Sale-wide price at `now` according to `pricing_mode`.
+    pub fn current_price(&self, now: i64) -> Result<u128> {
+        match self.pricing_mode {
+            PricingMode::Fixed => Ok(self.token_price),
+            PricingMode::DutchAuction {
+                start_price,
+                floor_price,
+                decay_per_second,
+                start_time,
+            } => {
+                let elapsed = now.saturating_sub(start_time).max(0) as u128;
+                let decay = decay_per_second.saturating_mul(elapsed);
+                Ok(start_price.saturating_sub(decay).max(floor_price))
+            }
+            PricingMode::BondingCurve {
+                base_price,
+                slope,
+                slope_step,
+            } => {
+                let increase = slope
+                    .checked_mul(self.token_sold)
+                    .ok_or(DcoError::MathOverflow)?
+                    / slope_step as u128;
+                Ok(base_price
+                    .checked_add(increase)
+                    .ok_or(DcoError::MathOverflow)?)
+            }
+        }
+    }
+
+    /// Average price of buying the next `amount` zktc base units at `now`.
+    /// On a bonding curve this is the integral over the purchase (see `bonding_curve_price`);
+    /// the other modes do not depend on `amount`.
+    pub fn purchase_price(&self, amount: u64, now: i64) -> Result<u128> {
+        match self.pricing_mode {
+            PricingMode::BondingCurve {
+                base_price,
+                slope,
+                slope_step,
+            } => bonding_curve_price(base_price, slope, slope_step, self.token_sold, amount),
+            _ => self.current_price(now),
+        }
+    }
+
+    pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+    /// Count `amount` against `releaser`'s quota for the current UTC day.
+    pub fn consume_releaser_quota(&mut self, releaser: &Pubkey, amount: u64, now: i64) -> Result<()> {
+        let quota = self
+            .releaser_quotas
+            .iter_mut()
+            .find(|q| q.releaser == *releaser)
+            .ok_or(DcoError::Unauthorized)?;
+
+        let day = now / Self::SECONDS_PER_DAY;
+        if quota.day != day {
+            quota.day = day;
+            quota.released_today = 0;
+        }
+
+        let released = quota
+            .released_today
+            .checked_add(amount)
+            .ok_or(DcoError::MathOverflow)?;
+        require!(released <= quota.daily_limit, DcoError::ReleaserQuotaExceeded);
+        quota.released_today = released;
+        Ok(())
+    }
+
+    /// The sale is successful once `soft_cap` is met and it either ended or hit `hard_cap`.
+    pub fn sale_succeeded(&self, now: i64) -> bool {
+        self.total_raised >= self.soft_cap
+            && (now >= self.dco_end_time || self.total_raised >= self.hard_cap)
+    }
+
+    /// The sale failed (refund mode) if it ended below `soft_cap`.
+    pub fn sale_failed(&self, now: i64) -> bool {
+        now >= self.dco_end_time && self.total_raised < self.soft_cap
+    }
+
+    /// Quote base units the treasury holds back for fair-launch commitments not yet claimed:
+    /// their whole deposit, released by `claim_commitment` as each one's refund is paid.
+    pub fn commitment_reserve(&self) -> u64 {
+        self.total_committed.saturating_sub(self.total_commit_settled)
+    }
+
+    /// Tokens allocated to vesting accounts and not yet claimed.
+    pub fn outstanding_vesting(&self) -> u128 {
+        self.total_vested.saturating_sub(self.total_claimed)
+    }
+
+    /// Tokens the vault still owes to vesting accounts, referrers, fair-launch buyers
+    /// and the ZCW (donations accounted but not yet sent by `donate_to_zcw`).
+    pub fn outstanding_obligations(&self) -> u128 {
+        self.outstanding_vesting()
+            .saturating_add(self.total_donations)
+            .saturating_add(
+                self.total_referral_accrued
+                    .saturating_sub(self.total_referral_claimed),
+            )
+            .saturating_add(self.fair_launch_reserved as u128)
+    }
+
+    /// Amount of `total` vested at `now`: `tge_unlock_bps` at `dco_end_time` (TGE),
+    /// then the rest linearly over `vesting_duration` once `vesting_cliff` has passed.
+    pub fn vested_amount(&self, total: u64, now: i64) -> Result<u64> {
+        let tge = self.dco_end_time;
+        if now < tge {
+            return Ok(0);
+        }
+
+        let unlocked_at_tge = (total as u128)
+            .checked_mul(self.tge_unlock_bps as u128)
+            .ok_or(DcoError::MathOverflow)?
+            / Self::BPS_DENOMINATOR as u128;
+
+        let linear_start = tge
+            .checked_add(self.vesting_cliff)
+            .ok_or(DcoError::MathOverflow)?;
+        if now < linear_start {
+            return Ok(unlocked_at_tge as u64);
+        }
+
+        let elapsed = now - linear_start;
+        if self.vesting_duration == 0 || elapsed >= self.vesting_duration {
+            return Ok(total);
+        }
+
+        let linear = (total as u128 - unlocked_at_tge)
+            .checked_mul(elapsed as u128)
+            .ok_or(DcoError::MathOverflow)?
+            / self.vesting_duration as u128;
+
+        Ok((unlocked_at_tge + linear) as u64)
+    }
+}
+
+/// Daily release quota of a releaser, kept alongside `DcoState.releasers`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct ReleaserQuota {
+    pub releaser: Pubkey,
+    pub daily_limit: u64,
+    pub day: i64,
+    pub released_today: u64,
+}
+
+impl ReleaserQuota {
+    pub const SPACE: usize = 32 + // releaser
+        8 + // daily_limit
+        8 + // day
+        8;  // released_today
+}
+
+/// How the sale-wide price is computed when no round is active
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PricingMode {
+    /// `token_price`
+    Fixed,
+    /// From `start_price` at `start_time`, down by `decay_per_second`, never below `floor_price`
+    DutchAuction {
+        start_price: u128,
+        floor_price: u128,
+        decay_per_second: u128,
+        start_time: i64,
+    },
+    /// `base_price + slope * token_sold / slope_step` at the margin;
+    /// a purchase pays the average over the tokens it buys (see `DcoState::purchase_price`)
+    BondingCurve {
+        base_price: u128,
+        slope: u128,
+        slope_step: u64,
+    },
+}
+
+impl PricingMode {
+    // largest variant (DutchAuction) + enum tag
+    pub const SPACE: usize = 1 + 16 + 16 + 16 + 8;
+
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            PricingMode::Fixed => {}
+            PricingMode::DutchAuction {
+                start_price,
+                floor_price,
+                ..
+            } => require!(
+                floor_price > 0 && start_price >= floor_price,
+                DcoError::InvalidPricingMode
+            ),
+            PricingMode::BondingCurve {
+                base_price,
+                slope_step,
+                ..
+            } => require!(
+                base_price > 0 && slope_step > 0,
+                DcoError::InvalidPricingMode
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// SOL/USD price feed read by `pegged_price`.
+/// Raw little-endian layout, so a local test program can stand in for a real oracle:
+/// - `price`: i64 at offset 0
+/// - `conf`: u64 at offset 8 (confidence interval, same unit as `price`)
+/// - `expo`: i32 at offset 16 (USD per SOL = `price * 10^expo`)
+/// - `publish_time`: i64 at offset 20 (unix timestamp)
+pub struct PriceFeed {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub publish_time: i64,
+}
+
+impl PriceFeed {
+    pub const LEN: usize = 8 + 8 + 4 + 8;
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        require!(data.len() >= Self::LEN, DcoError::InvalidPriceFeed);
+        Ok(Self {
+            price: i64::from_le_bytes(data[0..8].try_into().unwrap()),
+            conf: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            expo: i32::from_le_bytes(data[16..20].try_into().unwrap()),
+            publish_time: i64::from_le_bytes(data[20..28].try_into().unwrap()),
+        })
+    }
+}
+
+/// Donation beneficiary and its share of every `donate_to_zcw` payout
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct Beneficiary {
+    pub wallet: Pubkey,
+    pub weight_bps: u16,
+}
+
+impl Beneficiary {
+    pub const SPACE: usize = 32 + // wallet
+        2;  // weight_bps
+}
+
+/// Sale round (seed / private / public) under the `dco_state` PDA
+#[account]
+pub struct Round {
+    pub state: Pubkey,
+    pub index: u8,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub price: u128,
+    pub allocation: u64,
+    pub sold: u64,
+    pub allowlist: bool,
+    pub bump: u8,
+}
 
-    pub token_program: Program<'info, Token>,
+impl Round {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // state
+        1  + // index
+        8  + // start_time
+        8  + // end_time
+        16 + // price (u128)
+        8  + // allocation
+        8  + // sold
+        1  + // allowlist
+        1;   // bump
+
+    /// Count `amount` against this round's allocation.
+    pub fn record_sale(&mut self, amount: u64) -> Result<()> {
+        let sold = self
+            .sold
+            .checked_add(amount)
+            .ok_or(DcoError::MathOverflow)?;
+        require!(sold <= self.allocation, DcoError::RoundAllocationExceeded);
+        self.sold = sold;
+        Ok(())
+    }
 }
 
-#[derive(Accounts)]
-pub struct OnlyOwner<'info> {
-    #[account(mut, has_one = owner)]
-    pub state: Account<'info, DcoState>,
-    pub owner: Signer<'info>,
+/// Referral bonuses accrued by a referrer, paid out by `claim_referral`
+#[account]
+pub struct ReferralAccount {
+    pub referrer: Pubkey,
+    pub state: Pubkey,
+    pub accrued: u64,
+    pub claimed: u64,
+    pub bump: u8,
 }
 
-#[derive(Accounts)]
-pub struct OnlyOwnerAfterEnd<'info> {
-    #[account(
-        mut,
-        has_one = owner,
-        constraint = Clock::get()?.unix_timestamp >= state.dco_end_time @ DcoError::DcoNotActive
-    )]
-    pub state: Account<'info, DcoState>,
+impl ReferralAccount {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // referrer
+        32 + // state
+        8  + // accrued
+        8  + // claimed
+        1;   // bump
+}
 
-    pub owner: Signer<'info>,
+/// Audit record of a `release_zktc` call, one per off-chain payment reference
+#[account]
+pub struct ReleaseReceipt {
+    pub state: Pubkey,
+    pub payment_ref: [u8; 32],
+    pub caller: Pubkey,
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub donation_amount: u64,
+    pub slot: u64,
+    pub processed: bool,
+    pub bump: u8,
+}
 
-    #[account(mut, constraint = vault.mint == state.zk_token_mint @ DcoError::InvalidTokenMint)]
-    pub vault: Account<'info, TokenAccount>,
+impl ReleaseReceipt {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // state
+        32 + // payment_ref
+        32 + // caller
+        32 + // buyer
+        8  + // amount
+        8  + // donation_amount
+        8  + // slot
+        1  + // processed
+        1;   // bump
+}
 
-    #[account(
-        mut,
-        constraint = owner_token_account.mint == state.zk_token_mint @ DcoError::InvalidTokenMint,
-        constraint = owner_token_account.owner == owner.key() @ DcoError::Unauthorized
-    )]
-    pub owner_token_account: Account<'info, TokenAccount>,
+/// Per-buyer vesting allocation, paid out by `claim_vested`
+#[account]
+pub struct VestingAccount {
+    pub buyer: Pubkey,
+    pub state: Pubkey,
+    pub total_allocated: u64,
+    pub claimed: u64,
+    pub bump: u8,
+}
 
-    pub token_program: Program<'info, Token>,
+impl VestingAccount {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // buyer
+        32 + // state
+        8  + // total_allocated
+        8  + // claimed
+        1;   // bump
 }
 
-/// On-chain state
-#[account]
-pub struct DcoState {
+/// `DcoState` before `migrate_state`
+#[derive(AnchorDeserialize)]
+pub struct LegacyDcoState {
     pub owner: Pubkey,
     pub zk_token_mint: Pubkey,
     pub vault: Pubkey,
@@ -418,22 +3456,66 @@ pub struct DcoState {
     pub bump: u8,
 }
 
-impl DcoState {
-    pub const MAX_RELEASERS: usize = 20;
-    // computed size for the fields above
+/// Left by `finalize` at [b"dco_closed", state]; `initialize` refuses a state PDA that has one
+#[account]
+pub struct DcoTombstone {
+    pub state: Pubkey,
+    pub finalized_at: i64,
+    pub bump: u8,
+}
+
+impl DcoTombstone {
     pub const SPACE: usize = 8 + // discriminator
-        32 + // owner
-        32 + // zk_token_mint
-        32 + // vault
-        16 + // token_price (u128)
-        16 + // token_sold (u128)
-        16 + // total_donations (u128)
-        8  + // dco_end_time (i64)
-        32 + // zcw
-        4  + (32 * Self::MAX_RELEASERS) + // releasers vec (len + entries)
+        32 + // state
+        8  + // finalized_at
         1;   // bump
 }
 
+/// Per-buyer fair-launch deposit made through `commit`, settled by `claim_commitment`
+#[account]
+pub struct Commitment {
+    pub buyer: Pubkey,
+    pub state: Pubkey,
+    pub committed: u64,
+    pub claimed: bool,
+    pub bump: u8,
+    /// Referral account credited on the fill, `Pubkey::default()` if none
+    pub referral: Pubkey,
+}
+
+impl Commitment {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // buyer
+        32 + // state
+        8  + // committed
+        1  + // claimed
+        1  + // bump
+        32;  // referral
+}
+
+/// Per-buyer contribution made through `buy`, plus tokens released by `release_zktc`
+#[account]
+pub struct Contribution {
+    pub buyer: Pubkey,
+    pub state: Pubkey,
+    pub contributed: u64,
+    pub tokens_purchased: u64,
+    pub donations: u64,
+    pub bump: u8,
+    pub tokens_released: u64,
+}
+
+impl Contribution {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // buyer
+        32 + // state
+        8  + // contributed
+        8  + // tokens_purchased
+        8  + // donations
+        1  + // bump
+        8;   // tokens_released
+}
+
 #[error_code]
 pub enum DcoError {
     #[msg("You are not Authorized")]
@@ -472,6 +3554,106 @@ pub enum DcoError {
     ReleasersFull,
     #[msg("Invalid token mint")]
     InvalidTokenMint,
+    #[msg("Payment too small to buy a single token unit")]
+    PaymentTooSmall,
+    #[msg("Soft cap must not exceed a non-zero hard cap")]
+    InvalidCaps,
+    #[msg("Hard cap reached")]
+    HardCapReached,
+    #[msg("Soft cap not reached")]
+    SoftCapNotReached,
+    #[msg("Refunds are only available after a failed sale")]
+    RefundNotAvailable,
+    #[msg("Nothing to refund")]
+    NothingToRefund,
+    #[msg("Invalid vesting schedule")]
+    InvalidVestingSchedule,
+    #[msg("Nothing to claim")]
+    NothingToClaim,
+    #[msg("Invalid allowlist proof")]
+    InvalidMerkleProof,
+    #[msg("Purchase exceeds wallet allocation")]
+    AllocationExceeded,
+    #[msg("Invalid round window")]
+    InvalidRoundWindow,
+    #[msg("Round is not active")]
+    RoundNotActive,
+    #[msg("Active round account required")]
+    RoundRequired,
+    #[msg("Round allocation exceeded")]
+    RoundAllocationExceeded,
+    #[msg("Donation rate must not exceed 10000 bps")]
+    InvalidDonationBps,
+    #[msg("End time can only move forward")]
+    InvalidEndTime,
+    #[msg("End time extension too large")]
+    ExtensionTooLarge,
+    #[msg("Token price change too large")]
+    PriceChangeTooLarge,
+    #[msg("Releaser daily quota exceeded")]
+    ReleaserQuotaExceeded,
+    #[msg("DCO is paused")]
+    Paused,
+    #[msg("Donations must be sent to ZCW first")]
+    DonationsNotFlushed,
+    #[msg("Vested tokens are still unclaimed")]
+    VestingOutstanding,
+    #[msg("Refunds are still outstanding")]
+    RefundsOutstanding,
+    #[msg("Beneficiary weights must sum to 10000 bps")]
+    InvalidBeneficiaries,
+    #[msg("Beneficiary token accounts do not match the table")]
+    InvalidBeneficiaryAccounts,
+    #[msg("Invalid pricing mode")]
+    InvalidPricingMode,
+    #[msg("Sale already started")]
+    SaleAlreadyStarted,
+    #[msg("Price is above the buyer's max price")]
+    SlippageExceeded,
+    #[msg("Referral rate must not exceed 10000 bps")]
+    InvalidReferralBps,
+    #[msg("Buyer cannot refer themselves")]
+    SelfReferral,
+    #[msg("Referral bonuses are still unclaimed")]
+    ReferralsOutstanding,
+    #[msg("KYC credential required")]
+    KycRequired,
+    #[msg("Invalid KYC credential")]
+    InvalidKycCredential,
+    #[msg("Invalid quote mint")]
+    InvalidQuoteMint,
+    #[msg("Quote mint token accounts are required")]
+    QuoteAccountsRequired,
+    #[msg("Price feed account is required")]
+    PriceFeedRequired,
+    #[msg("Invalid price feed")]
+    InvalidPriceFeed,
+    #[msg("Price feed is stale")]
+    StalePriceFeed,
+    #[msg("Price feed confidence interval too wide")]
+    PriceConfidenceTooWide,
+    #[msg("Fair launch is not enabled")]
+    FairLaunchNotEnabled,
+    #[msg("Fair launch already settled")]
+    FairLaunchAlreadySettled,
+    #[msg("Fair launch not settled yet")]
+    FairLaunchNotSettled,
+    #[msg("Nothing committed")]
+    NothingCommitted,
+    #[msg("Commitment already claimed")]
+    CommitmentAlreadyClaimed,
+    #[msg("Fair-launch commitments are still unclaimed")]
+    CommitmentsOutstanding,
+    #[msg("Fair launch is not available with an allowlist or sale rounds")]
+    FairLaunchRestricted,
+    #[msg("The commitment's referral account is required")]
+    ReferralAccountRequired,
+    #[msg("This DCO was finalized and cannot be initialized again")]
+    SaleFinalized,
+    #[msg("Account already uses the current layout")]
+    AlreadyMigrated,
+    #[msg("Guardian and owner must be different keys")]
+    InvalidGuardian,
 }
 
 //
@@ -485,6 +3667,12 @@ pub struct Initialized {
     pub token_price: u128,
     pub dco_end_time: i64,
     pub zcw: Pubkey,
+    pub soft_cap: u64,
+    pub hard_cap: u64,
+    pub vesting_cliff: i64,
+    pub vesting_duration: i64,
+    pub tge_unlock_bps: u16,
+    pub donation_bps: u16,
 }
 
 #[event]
@@ -496,11 +3684,52 @@ pub struct SupplyInjected {
 #[event]
 pub struct ZktcReleased {
     pub caller: Pubkey,
+    pub payment_ref: [u8; 32],
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub donation_amount: u64,
+}
+
+#[event]
+pub struct ZktcPurchased {
     pub buyer: Pubkey,
     pub amount: u64,
+    pub paid: u64,
     pub donation_amount: u64,
 }
 
+#[event]
+pub struct RefundClaimed {
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub tokens_cancelled: u64,
+}
+
+#[event]
+pub struct VestedClaimed {
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub total_claimed: u64,
+}
+
+#[event]
+pub struct ReferrerRegistered {
+    pub referrer: Pubkey,
+}
+
+#[event]
+pub struct ReferralCredited {
+    pub referrer: Pubkey,
+    pub bonus: u64,
+    pub accrued: u64,
+}
+
+#[event]
+pub struct ReferralClaimed {
+    pub referrer: Pubkey,
+    pub amount: u64,
+}
+
 #[event]
 pub struct DonationSent {
     pub caller: Pubkey,
@@ -508,16 +3737,121 @@ pub struct DonationSent {
     pub amount: u64,
 }
 
+#[event]
+pub struct BeneficiaryDonationSent {
+    pub caller: Pubkey,
+    pub beneficiary: Pubkey,
+    pub token_account: Pubkey,
+    pub weight_bps: u16,
+    pub amount: u64,
+}
+
+#[event]
+pub struct BeneficiariesUpdated {
+    pub owner: Pubkey,
+    pub beneficiaries: Vec<Beneficiary>,
+}
+
 #[event]
 pub struct Withdrawn {
     pub owner: Pubkey,
     pub amount: u64,
 }
 
+#[event]
+pub struct TreasuryWithdrawn {
+    pub owner: Pubkey,
+    pub quote_mint: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct QuoteMintSet {
+    pub owner: Pubkey,
+    pub quote_mint: Pubkey,
+    pub quote_decimals: u8,
+    pub quote_treasury: Pubkey,
+}
+
+#[event]
+pub struct ZcwUpdated {
+    pub owner: Pubkey,
+    pub old_zcw: Pubkey,
+    pub new_zcw: Pubkey,
+}
+
+#[event]
+pub struct EndTimeExtended {
+    pub owner: Pubkey,
+    pub old_end_time: i64,
+    pub new_end_time: i64,
+}
+
+#[event]
+pub struct TokenPriceUpdated {
+    pub owner: Pubkey,
+    pub old_price: u128,
+    pub new_price: u128,
+}
+
+#[event]
+pub struct KycIssuerUpdated {
+    pub owner: Pubkey,
+    pub old_issuer: Pubkey,
+    pub new_issuer: Pubkey,
+}
+
+#[event]
+pub struct ReferralBpsUpdated {
+    pub owner: Pubkey,
+    pub old_bps: u16,
+    pub new_bps: u16,
+}
+
+#[event]
+pub struct PriceFeedUpdated {
+    pub owner: Pubkey,
+    pub price_feed: Pubkey,
+    pub max_price_age: i64,
+    pub max_price_conf_bps: u16,
+}
+
+#[event]
+pub struct PricingModeUpdated {
+    pub owner: Pubkey,
+    pub pricing_mode: PricingMode,
+}
+
+#[event]
+pub struct DonationBpsUpdated {
+    pub owner: Pubkey,
+    pub old_bps: u16,
+    pub new_bps: u16,
+}
+
+#[event]
+pub struct RoundAdded {
+    pub owner: Pubkey,
+    pub index: u8,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub price: u128,
+    pub allocation: u64,
+    pub allowlist: bool,
+}
+
+#[event]
+pub struct MerkleRootUpdated {
+    pub owner: Pubkey,
+    pub old_root: [u8; 32],
+    pub new_root: [u8; 32],
+}
+
 #[event]
 pub struct ReleaserAdded {
     pub owner: Pubkey,
     pub new_releaser: Pubkey,
+    pub daily_limit: u64,
 }
 
 #[event]
@@ -526,7 +3860,286 @@ pub struct ReleaserRemoved {
     pub old_releaser: Pubkey,
 }
 
-// add setter function for zcw and dco_end_time
+#[event]
+pub struct FairLaunchConfigured {
+    pub owner: Pubkey,
+    pub supply: u64,
+}
+
+#[event]
+pub struct Committed {
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub total_committed: u64,
+}
+
+#[event]
+pub struct FairLaunchSettled {
+    pub caller: Pubkey,
+    pub price: u128,
+    pub total_committed: u64,
+    pub tokens_reserved: u64,
+    pub raised: u64,
+}
+
+#[event]
+pub struct CommitmentClaimed {
+    pub buyer: Pubkey,
+    pub tokens: u64,
+    pub cost: u64,
+    pub refund: u64,
+}
+
+#[event]
+pub struct Finalized {
+    pub owner: Pubkey,
+    pub tokens_swept: u64,
+    pub quote_swept: u64,
+    pub treasury_lamports: u64,
+}
+
+#[event]
+pub struct GuardianUpdated {
+    pub owner: Pubkey,
+    pub old_guardian: Pubkey,
+    pub new_guardian: Pubkey,
+}
+
+#[event]
+pub struct DcoPaused {
+    pub caller: Pubkey,
+    pub reason: u8,
+}
+
+#[event]
+pub struct DcoUnpaused {
+    pub owner: Pubkey,
+    pub reason: u8,
+}
+
+#[event]
+pub struct OwnershipProposed {
+    pub owner: Pubkey,
+    pub pending_owner: Pubkey,
+}
+
+#[event]
+pub struct OwnershipTransferred {
+    pub old_owner: Pubkey,
+    pub new_owner: Pubkey,
+}
+
+#[event]
+pub struct StateMigrated {
+    pub owner: Pubkey,
+    pub state: Pubkey,
+}
+
 // Program Id: CEHTQCjD4A4z6MYRjXydYvFhnz6s5E5Wha8XvH9xvFXM
 
 // Signature: 2wADrwjyH2UhFiWV7DRjLVPf1StZEoSeWoQ1rk46Y9UYUP4J9zMkLAVN3x7Tw6SRWi5PK9RoLTSoc92N3fPq2oPi
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::program_pack::Pack;
+    use anchor_spl::metadata::mpl_token_metadata::types::Key;
+    use anchor_spl::token::spl_token;
+
+    const DECIMALS: u8 = 9;
+    const ONE: u64 = 1_000_000_000;
+
+    #[test]
+    fn bonding_curve_charges_the_average_price() {
+        // spot price goes 100 -> 300 over the first 2 zktc, average 200
+        let price = bonding_curve_price(100, 100, ONE, 0, 2 * ONE).unwrap();
+        assert_eq!(price, 200);
+        assert_eq!(bonding_curve_price(100, 100, ONE, 0, 0).unwrap(), 100);
+        assert_eq!(quote_for_tokens(2 * ONE, price, DECIMALS).unwrap(), 400);
+    }
+
+    #[test]
+    fn bonding_curve_split_orders_cost_the_same() {
+        let whole = quote_for_tokens(
+            4 * ONE,
+            bonding_curve_price(100, 100, ONE, 0, 4 * ONE).unwrap(),
+            DECIMALS,
+        )
+        .unwrap();
+        let first = quote_for_tokens(
+            ONE,
+            bonding_curve_price(100, 100, ONE, 0, ONE).unwrap(),
+            DECIMALS,
+        )
+        .unwrap();
+        let rest = quote_for_tokens(
+            3 * ONE,
+            bonding_curve_price(100, 100, ONE, ONE as u128, 3 * ONE).unwrap(),
+            DECIMALS,
+        )
+        .unwrap();
+        assert_eq!(first + rest, whole);
+    }
+
+    #[test]
+    fn size_purchase_never_costs_more_than_the_payment() {
+        let price_at = |amount| bonding_curve_price(100, 100, ONE, 0, amount);
+        for payment in [1u64, 99, 100, 101, 400, 12_345, 1_000_000] {
+            let (tokens, price) = size_purchase(payment, DECIMALS, price_at).unwrap();
+            assert_eq!(price, price_at(tokens).unwrap());
+            assert!(quote_for_tokens(tokens, price, DECIMALS).unwrap() <= payment);
+            // one more base unit would cost more than the payment
+            let next = tokens + 1;
+            assert!(quote_for_tokens(next, price_at(next).unwrap(), DECIMALS).unwrap() > payment);
+        }
+        let (tokens, price) = size_purchase(400, DECIMALS, price_at).unwrap();
+        assert_eq!((tokens, price), (2 * ONE, 200));
+    }
+
+    #[test]
+    fn tokens_for_quote_rounds_down_and_quote_for_tokens_rounds_up() {
+        assert_eq!(tokens_for_quote(1_000_000, 1_000_000, DECIMALS).unwrap(), ONE);
+        assert_eq!(quote_for_tokens(ONE, 1_000_000, DECIMALS).unwrap(), 1_000_000);
+
+        assert_eq!(tokens_for_quote(10, 3, 0).unwrap(), 3);
+        assert_eq!(tokens_for_quote(2, 3, 0).unwrap(), 0);
+        assert_eq!(quote_for_tokens(3, 3, 0).unwrap(), 9);
+        // 1 base unit of a 1-decimal token at 3 per whole token costs 0.3, charged as 1
+        assert_eq!(quote_for_tokens(1, 3, 1).unwrap(), 1);
+        assert!(tokens_for_quote(1, 0, 0).is_err());
+    }
+
+    /// Settle like `settle` does at a fixed price and claim every commitment:
+    /// returns each `(tokens, cost)` fill and the tokens reserved in the vault.
+    fn settle_and_claim(commits: &[u64], supply: u64, price: u128, decimals: u8) -> (Vec<(u64, u64)>, u64) {
+        let total: u64 = commits.iter().sum();
+        let sold = tokens_for_quote(total, price, decimals).unwrap().min(supply);
+        let fills = commits
+            .iter()
+            .map(|&c| fair_launch_allocation(c, total, supply, price, decimals).unwrap())
+            .collect();
+        (fills, sold)
+    }
+
+    #[test]
+    fn fair_launch_undersubscribed_fills_every_commitment_in_full() {
+        let commits = [1_000_000, 2_000_000, 500_000];
+        let (fills, sold) = settle_and_claim(&commits, 100 * ONE, 1_000_000, DECIMALS);
+        assert_eq!(fills, vec![(ONE, 1_000_000), (2 * ONE, 2_000_000), (ONE / 2, 500_000)]);
+        assert_eq!(sold, 3 * ONE + ONE / 2);
+    }
+
+    #[test]
+    fn fair_launch_oversubscribed_fills_pro_rata_rounded_down() {
+        // 10 tokens at 1 each, 30 committed: each is entitled to 10 * 10 / 30 = 3.33, filled 3
+        let (fills, sold) = settle_and_claim(&[10, 10, 10], 10, 1, 0);
+        assert_eq!(fills, vec![(3, 3), (3, 3), (3, 3)]);
+        let filled: u64 = fills.iter().map(|(tokens, _)| tokens).sum();
+        // the rounding dust stays in the vault
+        assert_eq!(sold, 10);
+        assert_eq!(sold - filled, 1);
+
+        // 7 * 10 / 30 = 2.33 -> 2, 23 * 10 / 30 = 7.67 -> 7
+        let (fills, _) = settle_and_claim(&[7, 23], 10, 1, 0);
+        assert_eq!(fills, vec![(2, 2), (7, 7)]);
+    }
+
+    #[test]
+    fn fair_launch_fills_never_exceed_supply_or_commitment() {
+        let cases: [(&[u64], u64, u128, u8); 5] = [
+            (&[1, 1, 1], 2, 3, 0),
+            (&[999, 1, 37, 4_000], 1_000, 7, 1),
+            (&[1_000_000, 3_333_333, 1], 5 * ONE, 1_000_001, DECIMALS),
+            (&[u32::MAX as u64, 12_345, 678], 3 * ONE, 999_999_937, DECIMALS),
+            (&[5, 5], 1_000, 3, 0),
+        ];
+        for (commits, supply, price, decimals) in cases {
+            let (fills, sold) = settle_and_claim(commits, supply, price, decimals);
+            let filled: u64 = fills.iter().map(|(tokens, _)| tokens).sum();
+            assert!(filled <= sold && sold <= supply);
+            for (&committed, &(_, cost)) in commits.iter().zip(&fills) {
+                assert!(cost <= committed);
+            }
+        }
+    }
+
+    #[test]
+    fn fair_launch_allocation_needs_commitments() {
+        assert!(fair_launch_allocation(0, 0, 10, 1, 0).is_err());
+    }
+
+    /// A credential as `soul_bound::mint_soulbound_nft` leaves it: one frozen token in the
+    /// holder's account, and the metadata Metaplex creates from `soul_bound::soulbound_data`
+    fn soulbound_credential(issuer: Pubkey, holder: Pubkey) -> (TokenAccount, Metadata) {
+        let mint = Pubkey::new_unique();
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account {
+            mint,
+            owner: holder,
+            amount: 1,
+            state: spl_token::state::AccountState::Frozen,
+            ..Default::default()
+        }
+        .pack_into_slice(&mut data);
+        let token_account = TokenAccount::try_deserialize(&mut &data[..]).unwrap();
+
+        let credential = soul_bound::soulbound_data(
+            "KYC".to_string(),
+            "KYC".to_string(),
+            "https://example.com/kyc.json".to_string(),
+            issuer,
+        );
+        let metadata = Metadata {
+            key: Key::MetadataV1,
+            update_authority: issuer,
+            mint,
+            name: credential.name,
+            symbol: credential.symbol,
+            uri: credential.uri,
+            seller_fee_basis_points: credential.seller_fee_basis_points,
+            creators: credential.creators,
+            primary_sale_happened: false,
+            is_mutable: false,
+            edition_nonce: None,
+            token_standard: None,
+            collection: credential.collection,
+            uses: credential.uses,
+            collection_details: None,
+            programmable_config: None,
+        };
+        (token_account, metadata)
+    }
+
+    #[test]
+    fn soulbound_credential_passes_check_kyc() {
+        let issuer = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        let (token_account, metadata) = soulbound_credential(issuer, buyer);
+        check_kyc(issuer, buyer, Some(&token_account), Some(&metadata)).unwrap();
+    }
+
+    #[test]
+    fn check_kyc_rejects_other_issuers_and_holders() {
+        let issuer = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        let (token_account, metadata) = soulbound_credential(issuer, buyer);
+        assert_eq!(
+            check_kyc(Pubkey::new_unique(), buyer, Some(&token_account), Some(&metadata)).unwrap_err(),
+            DcoError::InvalidKycCredential.into()
+        );
+        assert_eq!(
+            check_kyc(issuer, Pubkey::new_unique(), Some(&token_account), Some(&metadata)).unwrap_err(),
+            DcoError::InvalidKycCredential.into()
+        );
+
+        // naming the issuer without its signature proves nothing
+        let mut unverified = metadata.clone();
+        for creator in unverified.creators.iter_mut().flatten() {
+            creator.verified = false;
+        }
+        assert_eq!(
+            check_kyc(issuer, buyer, Some(&token_account), Some(&unverified)).unwrap_err(),
+            DcoError::InvalidKycCredential.into()
+        );
+    }
+}
@@ -1,6 +1,16 @@
+// Anchor 0.31 emits its IDL instruction handlers outside the `#[program]` module and they
+// call the deprecated AccountInfo::realloc, so this allow cannot be scoped to that module.
+#![allow(deprecated)]
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Mint, MintTo, Transfer};
-use anchor_spl::metadata::{create_metadata_accounts_v3, CreateMetadataAccountsV3, Metadata};
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_spl::associated_token::{self, AssociatedToken};
+use anchor_spl::token::{self, Token, TokenAccount, Mint, MintTo, Transfer, InitializeMint2, FreezeAccount};
+use anchor_spl::metadata::{
+    create_master_edition_v3, create_metadata_accounts_v3, CreateMasterEditionV3,
+    CreateMetadataAccountsV3, Metadata,
+};
 use mpl_token_metadata::types::DataV2;
 
 
@@ -14,6 +24,7 @@ pub mod engines {
     use super::*;
 
     /// Initialize the Engines program
+    /// `period_durations` are the lock durations in seconds for D30, D60, D180 and D365
     pub fn initialize(
         ctx: Context<Initialize>,
         zcw: Pubkey,
@@ -21,7 +32,9 @@ pub mod engines {
         uri_60_days: String,
         uri_180_days: String,
         uri_365_days: String,
+        period_durations: [u64; 4],
     ) -> Result<()> {
+        validate_period_durations(&period_durations)?;
         let state = &mut ctx.accounts.state;
 
         state.authority = ctx.accounts.authority.key();
@@ -34,14 +47,22 @@ pub mod engines {
         state.next_lock_id = 1;
         state.reserve_for_donation = 0;
         state.paused = false;
+        state.keeper = Pubkey::default();
+        state.soulbound_badges = false;
+        state.zktc_decimals = ctx.accounts.zktc_mint.decimals;
+        state.burn_badge_threshold = DEFAULT_BURN_BADGE_THRESHOLD;
+        state.burn_badge_tiers = Vec::new();
         state.bump = ctx.bumps.state;
 
         // Set default donation rates (in basis points)
         state.donation_rates = [50, 100, 150, 250]; // 0.5%, 1%, 1.5%, 2.5%
         state.scale = 10000;
 
-        // Set period durations in seconds (for testing: minutes instead of days)
-        state.period_durations = [60, 120, 180, 240]; // 1, 2, 3, 4 minutes
+        // Set default early unlock penalties (in basis points, scaled by the time left)
+        state.early_unlock_penalties = DEFAULT_EARLY_UNLOCK_PENALTIES;
+
+        // Set period durations in seconds
+        state.period_durations = period_durations;
 
         // set URIs
         state.uri_30_days = uri_30_days;
@@ -55,6 +76,12 @@ pub mod engines {
             zcw,
         });
 
+        emit!(BurnBadgeThresholdUpdated {
+            old_threshold: 0,
+            new_threshold: state.burn_badge_threshold,
+            zktc_decimals: state.zktc_decimals,
+        });
+
         Ok(())
     }
 
@@ -66,7 +93,8 @@ pub mod engines {
         let state = &mut ctx.accounts.state;
         let clock = Clock::get()?;
 
-        if matches!(period, Period::D180 | Period::D355) {
+        let mut reserved_donation = 0u64;
+        if matches!(period, Period::D180 | Period::D365) {
             let rate = state.donation_rates[period as usize];
             let donation = (amount as u128 * rate as u128) / state.scale as u128;
             let donation_u64 = u64::try_from(donation).map_err(|_| EnginesError::AmountTooLarge)?;
@@ -85,6 +113,7 @@ pub mod engines {
                 .reserve_for_donation
                 .checked_add(donation_u64)
                 .ok_or(EnginesError::MathOverflow)?;
+            reserved_donation = donation_u64;
         }
 
         let cpi_accounts = Transfer {
@@ -102,21 +131,31 @@ pub mod engines {
             .checked_add(1)
             .ok_or(EnginesError::MathOverflow)?;
 
-        let lock_position = LockPosition {
-            id: lock_id,
-            amount,
-            start: clock.unix_timestamp as u64,
-            period,
-            unlocked: false,
-        };
+        let lock_position = &mut ctx.accounts.lock_position;
+        lock_position.owner = ctx.accounts.user.key();
+        lock_position.state = state.key();
+        lock_position.id = lock_id;
+        lock_position.amount = amount;
+        lock_position.start = clock.unix_timestamp as u64;
+        lock_position.period = period;
+        lock_position.duration = state.period_durations[period as usize];
+        lock_position.reserved_donation = reserved_donation;
+        lock_position.bump = ctx.bumps.lock_position;
 
         let user_locks = &mut ctx.accounts.user_data;
-        require!(
-            user_locks.locks.len() < MAX_LOCKS_PER_USER,
-            EnginesError::TooManyLocks
-        );
-        user_locks.locks.push(lock_position);
         user_locks.owner = ctx.accounts.user.key();
+        user_locks.lock_count = user_locks
+            .lock_count
+            .checked_add(1)
+            .ok_or(EnginesError::MathOverflow)?;
+        user_locks.active_locks = user_locks
+            .active_locks
+            .checked_add(1)
+            .ok_or(EnginesError::MathOverflow)?;
+        user_locks.total_locked = user_locks
+            .total_locked
+            .checked_add(amount)
+            .ok_or(EnginesError::MathOverflow)?;
 
         state.total_locked = state
             .total_locked
@@ -134,60 +173,192 @@ pub mod engines {
         Ok(())
     }
 
-    /// Unlock a specific lock by ID
+    /// Unlock a specific lock by ID, closing its lock PDA (rent goes back to the user)
+    /// Signed by the lock owner or the keeper; the state PDA signs all vault transfers and the badge mint
     pub fn unlock_tokens(ctx: Context<UnlockTokens>, lock_id: u64) -> Result<()> {
-        require!(!ctx.accounts.state.paused, EnginesError::Paused);
-        let mut ctx = ctx;
-
-        let user_locks = &mut ctx.accounts.user_data;
+        require!(!ctx.accounts.common.state.paused, EnginesError::Paused);
+        require!(
+            ctx.accounts.lock_position.id == lock_id,
+            EnginesError::BadLockId
+        );
 
-        let mut found_lock = None;
-        for (index, lock) in user_locks.locks.iter().enumerate() {
-            if lock.id == lock_id && !lock.unlocked {
-                found_lock = Some(index);
-                break;
-            }
-        }
+        let badge = BadgeAccounts {
+            badge_mint: ctx.accounts.badge_mint.to_account_info(),
+            user_badge_token_account: ctx.accounts.user_badge_token_account.to_account_info(),
+            metadata_account: ctx.accounts.metadata_account.to_account_info(),
+            master_edition: ctx.accounts.master_edition.to_account_info(),
+        };
+        unlock_position(&mut ctx.accounts.common, &ctx.accounts.lock_position, &badge)?;
 
-        let lock_index = found_lock.ok_or(EnginesError::BadLockId)?;
-        unlock_by_index(&mut ctx, lock_index)?;
+        ctx.accounts
+            .lock_position
+            .close(ctx.accounts.common.user.to_account_info())?;
 
         Ok(())
     }
 
-    /// Unlock all matured locks for a user
-    pub fn unlock_all_matured(ctx: Context<UnlockTokens>) -> Result<()> {
-        require!(!ctx.accounts.state.paused, EnginesError::Paused);
-        let mut ctx = ctx;
+    /// Unlock all matured locks passed as remaining accounts, closing each lock PDA
+    /// Signed by the lock owner or the keeper
+    /// remaining_accounts, per lock: [lock_position, badge_mint, user_badge_token_account, metadata_account, master_edition]
+    /// Locks that have not matured yet are skipped
+    pub fn unlock_all_matured<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UnlockAllMatured<'info>>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.common.state.paused, EnginesError::Paused);
+        require!(
+            !ctx.remaining_accounts.is_empty()
+                && ctx
+                    .remaining_accounts
+                    .chunks(ACCOUNTS_PER_UNLOCK)
+                    .all(|chunk| chunk.len() == ACCOUNTS_PER_UNLOCK),
+            EnginesError::InvalidRemainingAccounts
+        );
 
-        let user_locks = &mut ctx.accounts.user_data;
         let clock = Clock::get()?;
-        let durations = ctx.accounts.state.period_durations;
+        let user_key = ctx.accounts.common.user.key();
         let mut unlocked_count = 0u32;
-        let mut indices_to_unlock: Vec<usize> = Vec::new();
 
-        for (index, lock) in user_locks.locks.iter().enumerate() {
-            if !lock.unlocked && is_matured(lock.start, lock.period, &durations, clock.unix_timestamp as u64) {
-                indices_to_unlock.push(index);
+        for chunk in ctx.remaining_accounts.chunks(ACCOUNTS_PER_UNLOCK) {
+            // owner + discriminator checked here; lock PDAs are only ever created by lock_tokens
+            let lock_position = Account::<'info, LockPosition>::try_from(&chunk[0])?;
+            require!(lock_position.owner == user_key, EnginesError::BadLockId);
+            require_keys_eq!(
+                lock_position.state,
+                ctx.accounts.common.state.key(),
+                EnginesError::InvalidLockState
+            );
+
+            if !is_matured(
+                lock_position.start,
+                lock_position.duration,
+                clock.unix_timestamp as u64,
+            ) {
+                continue;
             }
-        }
 
-        require!(!indices_to_unlock.is_empty(), EnginesError::LockNotMatured);
+            let badge = BadgeAccounts {
+                badge_mint: chunk[1].clone(),
+                user_badge_token_account: chunk[2].clone(),
+                metadata_account: chunk[3].clone(),
+                master_edition: chunk[4].clone(),
+            };
+            unlock_position(&mut ctx.accounts.common, &lock_position, &badge)?;
+            lock_position.close(ctx.accounts.common.user.to_account_info())?;
 
-        for &index in indices_to_unlock.iter().rev() {
-            unlock_by_index(&mut ctx, index)?;
             unlocked_count = unlocked_count.checked_add(1).ok_or(EnginesError::MathOverflow)?;
         }
 
+        require!(unlocked_count > 0, EnginesError::LockNotMatured);
+
         emit!(UnlockedAllMatured {
-            user: ctx.accounts.user.key(),
+            user: user_key,
             unlocked_count,
         });
 
         Ok(())
     }
 
+    /// Exit a lock before maturity (lock owner only), closing its lock PDA
+    /// The penalty is `early_unlock_penalties[period]` scaled by the share of the lock duration left,
+    /// sent to the ZCW token account; the donation reserved for this lock is released. No badge is minted
+    pub fn early_unlock(ctx: Context<EarlyUnlock>, lock_id: u64) -> Result<()> {
+        require!(!ctx.accounts.common.state.paused, EnginesError::Paused);
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp as u64;
+
+        let lock = &ctx.accounts.lock_position;
+        require!(lock.id == lock_id, EnginesError::BadLockId);
+        require!(
+            !is_matured(lock.start, lock.duration, now),
+            EnginesError::LockAlreadyMatured
+        );
+
+        let common = &mut ctx.accounts.common;
+        let state_info = common.state.to_account_info();
+        let state = &mut common.state;
+
+        let penalty = early_unlock_penalty(
+            lock,
+            state.early_unlock_penalties[lock.period as usize],
+            state.scale,
+            now,
+        )?;
+        let to_user = lock
+            .amount
+            .checked_sub(penalty)
+            .ok_or(EnginesError::MathOverflow)?;
+
+        state.total_locked = state
+            .total_locked
+            .checked_sub(lock.amount)
+            .ok_or(EnginesError::MathOverflow)?;
+        state.total_unlocked = state
+            .total_unlocked
+            .checked_add(to_user)
+            .ok_or(EnginesError::MathOverflow)?;
+        // the matching donation will never be paid for this lock
+        state.reserve_for_donation = state
+            .reserve_for_donation
+            .saturating_sub(lock.reserved_donation);
+
+        let authority_key = state.authority;
+        let bump = state.bump;
+        let seeds: &[&[u8]] = &[b"engines_state".as_ref(), authority_key.as_ref(), &[bump]];
+        let signer_seeds: &[&[&[u8]]] = &[seeds];
+
+        // Send penalty to ZCW
+        if penalty > 0 {
+            let cpi_accounts = Transfer {
+                from: common.vault.to_account_info(),
+                to: common.zcw_token_account.to_account_info(),
+                authority: state_info.clone(),
+            };
+            let cpi_program = common.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, penalty)?;
+        }
+
+        // Return remaining tokens to user
+        {
+            let cpi_accounts = Transfer {
+                from: common.vault.to_account_info(),
+                to: common.user_token_account.to_account_info(),
+                authority: state_info.clone(),
+            };
+            let cpi_program = common.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, to_user)?;
+        }
+
+        let user_locks = &mut common.user_data;
+        user_locks.active_locks = user_locks
+            .active_locks
+            .checked_sub(1)
+            .ok_or(EnginesError::MathOverflow)?;
+        user_locks.total_locked = user_locks
+            .total_locked
+            .checked_sub(lock.amount)
+            .ok_or(EnginesError::MathOverflow)?;
+
+        emit!(EarlyUnlocked {
+            user: common.user.key(),
+            lock_id,
+            returned_to_user: to_user,
+            penalty_to_zcw: penalty,
+            released_reserve: lock.reserved_donation,
+        });
+
+        ctx.accounts
+            .lock_position
+            .close(ctx.accounts.common.user.to_account_info())?;
+
+        Ok(())
+    }
+
     /// Burn tokens to give (Burn-to-Give functionality)
+    /// Burns of at least `burn_badge_threshold` whole zktc mint a Burn-to-Give badge NFT; its URI
+    /// is the highest `burn_badge_tiers` entry reached by the user's cumulative `total_burned`.
+    /// The badge accounts are only required for such burns, and only once tiers are set
     pub fn burn_to_give(ctx: Context<BurnToGiveContext>, amount: u64) -> Result<()> {
         require!(amount > 0, EnginesError::InvalidAmount);
         require!(!ctx.accounts.state.paused, EnginesError::Paused);
@@ -226,9 +397,10 @@ pub mod engines {
         token::transfer(cpi_ctx, amount)?;
 
         let bump = state.bump;
+        let authority = state.authority;
         let seeds: &[&[u8]] = &[
             b"engines_state".as_ref(),
-            state.authority.as_ref(),
+            authority.as_ref(),
             &[bump],
         ];
         let signer_seeds: &[&[&[u8]]] = &[seeds];
@@ -248,17 +420,84 @@ pub mod engines {
             .ok_or(EnginesError::MathOverflow)?;
         user_burn_data.last_burn_timestamp = clock.unix_timestamp as u64;
 
+        // highest tier reached by the cumulative burn; tiers are ascending and start at 0.
+        // Until tiers are set, burns are recorded without a badge
+        let mut badge_uri = None;
+        if amount >= to_base_units(state.burn_badge_threshold, state.zktc_decimals)? {
+            for tier in state.burn_badge_tiers.iter() {
+                if user_burn_data.total_burned >= to_base_units(tier.min_total_burned, state.zktc_decimals)? {
+                    badge_uri = Some(tier.uri.clone());
+                }
+            }
+        }
+
         let mut badge_id = 0u64;
-        if amount >= 10000 {
+        if let Some(uri) = badge_uri {
             badge_id = state.next_badge_id;
             state.next_badge_id = state.next_badge_id
                 .checked_add(1)
                 .ok_or(EnginesError::MathOverflow)?;
 
+            // the mint PDA is per user, so concurrent burns by other users cannot collide
+            let badge_index = user_burn_data.burn_badge_count.to_le_bytes();
             user_burn_data.burn_badge_count = user_burn_data
                 .burn_badge_count
                 .checked_add(1)
                 .ok_or(EnginesError::MathOverflow)?;
+
+            let (
+                Some(badge_mint),
+                Some(user_badge_token_account),
+                Some(metadata_account),
+                Some(master_edition),
+                Some(associated_token_program),
+                Some(metadata_program),
+            ) = (
+                ctx.accounts.badge_mint.as_ref(),
+                ctx.accounts.user_badge_token_account.as_ref(),
+                ctx.accounts.metadata_account.as_ref(),
+                ctx.accounts.master_edition.as_ref(),
+                ctx.accounts.associated_token_program.as_ref(),
+                ctx.accounts.metadata_program.as_ref(),
+            ) else {
+                return err!(EnginesError::BadgeAccountsRequired);
+            };
+
+            let badge = BadgeAccounts {
+                badge_mint: badge_mint.to_account_info(),
+                user_badge_token_account: user_badge_token_account.to_account_info(),
+                metadata_account: metadata_account.to_account_info(),
+                master_edition: master_edition.to_account_info(),
+            };
+            let programs = BadgePrograms {
+                payer: ctx.accounts.user.to_account_info(),
+                user: ctx.accounts.user.to_account_info(),
+                state: state_info.clone(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+                associated_token_program: associated_token_program.to_account_info(),
+                metadata_program: metadata_program.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                rent: ctx.accounts.rent.to_account_info(),
+            };
+            let state_key = state_info.key();
+            let user_key = ctx.accounts.user.key();
+            mint_badge(
+                &programs,
+                &badge,
+                signer_seeds,
+                &[b"burn_badge".as_ref(), state_key.as_ref(), user_key.as_ref(), badge_index.as_ref()],
+                badge_id,
+                DataV2 {
+                    name: format!("Burn-to-Give Badge #{}", badge_id),
+                    symbol: "BTGB".to_string(),
+                    uri,
+                    seller_fee_basis_points: 0,
+                    creators: None,
+                    collection: None,
+                    uses: None,
+                },
+                state.soulbound_badges,
+            )?;
         }
 
         emit!(BurnToGiveEvent {
@@ -306,6 +545,108 @@ pub mod engines {
         Ok(())
     }
 
+    /// Set the early unlock penalty for a specific period, in basis points (owner only)
+    pub fn set_early_unlock_penalty(ctx: Context<OnlyAuthority>, period: Period, new_penalty: u16) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        require!(new_penalty <= state.scale, EnginesError::InvalidPenalty);
+        let old_penalty = state.early_unlock_penalties[period as usize];
+        state.early_unlock_penalties[period as usize] = new_penalty;
+
+        emit!(EarlyUnlockPenaltyUpdated {
+            period,
+            old_penalty,
+            new_penalty,
+        });
+
+        Ok(())
+    }
+
+    /// Set lock durations in seconds per period (owner only)
+    /// Only affects new locks; existing locks keep the duration stored at lock time
+    pub fn set_period_durations(ctx: Context<OnlyAuthority>, period_durations: [u64; 4]) -> Result<()> {
+        validate_period_durations(&period_durations)?;
+        let state = &mut ctx.accounts.state;
+        let old_durations = state.period_durations;
+        state.period_durations = period_durations;
+
+        emit!(PeriodDurationsUpdated {
+            old_durations,
+            new_durations: period_durations,
+        });
+
+        Ok(())
+    }
+
+    /// Set the keeper allowed to unlock matured locks on behalf of users (owner only)
+    /// Pass Pubkey::default() to disable
+    pub fn set_keeper(ctx: Context<OnlyAuthority>, new_keeper: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        let old_keeper = state.keeper;
+        state.keeper = new_keeper;
+
+        emit!(KeeperUpdated {
+            old_keeper,
+            new_keeper,
+        });
+
+        Ok(())
+    }
+
+    /// Set the minimum single burn, in whole zktc, that mints a Burn-to-Give badge (owner only)
+    pub fn set_burn_badge_threshold(ctx: Context<OnlyAuthority>, new_threshold: u64) -> Result<()> {
+        require!(new_threshold > 0, EnginesError::InvalidAmount);
+        let state = &mut ctx.accounts.state;
+        // must stay representable in base units
+        to_base_units(new_threshold, state.zktc_decimals)?;
+        let old_threshold = state.burn_badge_threshold;
+        state.burn_badge_threshold = new_threshold;
+
+        emit!(BurnBadgeThresholdUpdated {
+            old_threshold,
+            new_threshold,
+            zktc_decimals: state.zktc_decimals,
+        });
+
+        Ok(())
+    }
+
+    /// Set the Burn-to-Give badge URI tiers by cumulative burn in whole zktc (owner only)
+    /// Tiers must start at 0 and be strictly ascending
+    pub fn set_burn_badge_tiers(ctx: Context<OnlyAuthority>, tiers: Vec<BurnBadgeTier>) -> Result<()> {
+        require!(
+            !tiers.is_empty() && tiers.len() <= MAX_BURN_BADGE_TIERS,
+            EnginesError::InvalidBurnBadgeTiers
+        );
+        require!(tiers[0].min_total_burned == 0, EnginesError::InvalidBurnBadgeTiers);
+        for pair in tiers.windows(2) {
+            require!(
+                pair[0].min_total_burned < pair[1].min_total_burned,
+                EnginesError::InvalidBurnBadgeTiers
+            );
+        }
+        for tier in tiers.iter() {
+            require!(
+                tier.uri.len() <= MAX_URI_LEN,
+                EnginesError::InvalidBurnBadgeTiers
+            );
+        }
+
+        ctx.accounts.state.burn_badge_tiers = tiers.clone();
+
+        emit!(BurnBadgeTiersUpdated { tiers });
+
+        Ok(())
+    }
+
+    /// Make newly minted badges soulbound (non-transferable) or transferable (owner only)
+    pub fn set_soulbound_badges(ctx: Context<OnlyAuthority>, soulbound: bool) -> Result<()> {
+        ctx.accounts.state.soulbound_badges = soulbound;
+
+        emit!(SoulboundBadgesUpdated { soulbound });
+
+        Ok(())
+    }
+
     /// Pause/unpause the contract (owner only)
     pub fn set_paused(ctx: Context<OnlyAuthority>, paused: bool) -> Result<()> {
         ctx.accounts.state.paused = paused;
@@ -347,224 +688,636 @@ pub mod engines {
 
         Ok(())
     }
-}
 
-/// Helper: unlock by index (with NFT metadata selection)
-fn unlock_by_index(ctx: &mut Context<UnlockTokens>, index: usize) -> Result<()> {
-    let clock = Clock::get()?;
-    let state_info = ctx.accounts.state.to_account_info();
-    let state = &mut ctx.accounts.state;
+    /// Migrate an `EnginesState` created before keepers, early unlock penalties, soulbound badges and
+    /// burn badge settings existed (owner only): grows it to `EnginesState::SPACE`, with the owner
+    /// paying the extra rent, and fills those fields with the `initialize` defaults
+    pub fn migrate_state(ctx: Context<MigrateState>) -> Result<()> {
+        let state_info = ctx.accounts.state.to_account_info();
+        require!(
+            state_info.data_len() < EnginesState::SPACE,
+            EnginesError::AlreadyMigrated
+        );
+        let legacy = {
+            let data = state_info.try_borrow_data()?;
+            require!(
+                data.get(..8) == Some(EnginesState::DISCRIMINATOR),
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            LegacyEnginesState::deserialize(&mut &data[8..])
+                .map_err(|_| ErrorCode::AccountDidNotDeserialize)?
+        };
+        require_keys_eq!(
+            legacy.zktc_mint,
+            ctx.accounts.zktc_mint.key(),
+            EnginesError::InvalidZktcMint
+        );
 
-    {
-        let user_locks = &mut ctx.accounts.user_data;
-        require!(index < user_locks.locks.len(), EnginesError::BadLockId);
+        let lamports = Rent::get()?.minimum_balance(EnginesState::SPACE);
+        let top_up = lamports.saturating_sub(state_info.lamports());
+        if top_up > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.authority.to_account_info(),
+                to: state_info.clone(),
+            };
+            let cpi_ctx = CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_ctx, top_up)?;
+        }
+        state_info.resize(EnginesState::SPACE)?;
+
+        let state = EnginesState {
+            authority: legacy.authority,
+            zktc_mint: legacy.zktc_mint,
+            zcw: legacy.zcw,
+            vault: legacy.vault,
+            total_locked: legacy.total_locked,
+            total_unlocked: legacy.total_unlocked,
+            next_badge_id: legacy.next_badge_id,
+            next_lock_id: legacy.next_lock_id,
+            reserve_for_donation: legacy.reserve_for_donation,
+            paused: legacy.paused,
+            keeper: Pubkey::default(),
+            soulbound_badges: false,
+            zktc_decimals: ctx.accounts.zktc_mint.decimals,
+            burn_badge_threshold: DEFAULT_BURN_BADGE_THRESHOLD,
+            burn_badge_tiers: Vec::new(),
+            donation_rates: legacy.donation_rates,
+            early_unlock_penalties: DEFAULT_EARLY_UNLOCK_PENALTIES,
+            scale: legacy.scale,
+            period_durations: legacy.period_durations,
+            bump: legacy.bump,
+            uri_30_days: legacy.uri_30_days,
+            uri_60_days: legacy.uri_60_days,
+            uri_180_days: legacy.uri_180_days,
+            uri_365_days: legacy.uri_365_days,
+        };
+        let mut data = state_info.try_borrow_mut_data()?;
+        state.try_serialize(&mut &mut data[..])?;
 
-        {
-            let lock = &mut user_locks.locks[index];
-            require!(!lock.unlocked, EnginesError::AlreadyUnlocked);
+        emit!(StateMigrated {
+            authority: state.authority,
+            state: state_info.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Migrate a `UserLockData` from the layout that kept every lock in a vector (lock owner or
+    /// state owner): each entry still locked moves to its own `LockPosition` PDA with the same id and
+    /// start, the period duration and matching reserve of `state`, and the account shrinks to the
+    /// per-user totals, refunding the freed rent to the user. The caller pays for the lock PDAs.
+    /// The legacy layout does not record its state, so `state` must be the one of the program
+    /// upgrade authority, which deployed and initialized the legacy program
+    /// remaining_accounts: the lock PDAs of the still-locked entries, in vector order
+    pub fn migrate_user_locks<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MigrateUserLocks<'info>>,
+    ) -> Result<()> {
+        let user_data_info = ctx.accounts.user_data.to_account_info();
+        require!(
+            user_data_info.data_len() > UserLockData::SPACE,
+            EnginesError::AlreadyMigrated
+        );
+        let legacy = {
+            let data = user_data_info.try_borrow_data()?;
             require!(
-                is_matured(
-                    lock.start,
-                    lock.period,
-                    &state.period_durations,
-                    clock.unix_timestamp as u64
-                ),
-                EnginesError::LockNotMatured
+                data.get(..8) == Some(UserLockData::DISCRIMINATOR),
+                ErrorCode::AccountDiscriminatorMismatch
             );
+            LegacyUserLockData::deserialize(&mut &data[8..])
+                .map_err(|_| ErrorCode::AccountDidNotDeserialize)?
+        };
 
-            let rate = state.donation_rates[lock.period as usize];
-            let donation = (lock.amount as u128 * rate as u128) / state.scale as u128;
-            let donation_u64 =
-                u64::try_from(donation).map_err(|_| EnginesError::AmountTooLarge)?;
-            let to_user = lock
-                .amount
-                .checked_sub(donation_u64)
-                .ok_or(EnginesError::MathOverflow)?;
+        let open_locks: Vec<&LegacyLockPosition> =
+            legacy.locks.iter().filter(|lock| !lock.unlocked).collect();
+        require!(
+            ctx.remaining_accounts.len() == open_locks.len(),
+            EnginesError::InvalidRemainingAccounts
+        );
 
-            lock.unlocked = true;
-            state.total_locked = state
-                .total_locked
-                .checked_sub(lock.amount)
-                .ok_or(EnginesError::MathOverflow)?;
-            state.total_unlocked = state
-                .total_unlocked
-                .checked_add(to_user)
+        let state = &ctx.accounts.state;
+        let state_key = state.key();
+        let user_key = ctx.accounts.user.key();
+        let mut total_locked = 0u64;
+        for (lock, lock_info) in open_locks.iter().zip(ctx.remaining_accounts.iter()) {
+            let lock_id = lock.id.to_le_bytes();
+            let (lock_key, lock_bump) = Pubkey::find_program_address(
+                &[
+                    b"lock".as_ref(),
+                    state_key.as_ref(),
+                    user_key.as_ref(),
+                    lock_id.as_ref(),
+                ],
+                &crate::ID,
+            );
+            require_keys_eq!(
+                lock_info.key(),
+                lock_key,
+                EnginesError::InvalidRemainingAccounts
+            );
+            let seeds: &[&[u8]] = &[
+                b"lock".as_ref(),
+                state_key.as_ref(),
+                user_key.as_ref(),
+                lock_id.as_ref(),
+                &[lock_bump],
+            ];
+            create_pda_account(
+                &ctx.accounts.caller.to_account_info(),
+                lock_info,
+                &ctx.accounts.system_program.to_account_info(),
+                LockPosition::SPACE,
+                &crate::ID,
+                &[seeds],
+            )?;
+
+            // same reserve `lock_tokens` takes, released again on unlock
+            let reserved_donation = if matches!(lock.period, Period::D180 | Period::D365) {
+                let rate = state.donation_rates[lock.period as usize];
+                let donation = (lock.amount as u128 * rate as u128) / state.scale as u128;
+                u64::try_from(donation).map_err(|_| EnginesError::AmountTooLarge)?
+            } else {
+                0
+            };
+            let position = LockPosition {
+                owner: user_key,
+                state: state_key,
+                id: lock.id,
+                amount: lock.amount,
+                start: lock.start,
+                period: lock.period,
+                duration: state.period_durations[lock.period as usize],
+                bump: lock_bump,
+                reserved_donation,
+            };
+            let mut data = lock_info.try_borrow_mut_data()?;
+            position.try_serialize(&mut &mut data[..])?;
+
+            total_locked = total_locked
+                .checked_add(lock.amount)
                 .ok_or(EnginesError::MathOverflow)?;
+        }
 
-            let authority_key = state.authority;
-            let bump = state.bump;
-            let seeds: &[&[u8]] =
-                &[b"engines_state".as_ref(), authority_key.as_ref(), &[bump]];
-            let signer_seeds: &[&[&[u8]]] = &[seeds];
-
-            let mut matched = false;
-            let mut match_amount = 0u64;
-
-            // Matching donation
-            if matches!(lock.period, Period::D180 | Period::D355) {
-                let available = free_treasury(ctx.accounts.vault.amount, state.total_locked);
-                require!(
-                    available >= donation_u64,
-                    EnginesError::InsufficientMatchingTreasury
-                );
-
-                let cpi_accounts = Transfer {
-                    from: ctx.accounts.vault.to_account_info(),
-                    to: ctx.accounts.zcw_token_account.to_account_info(),
-                    authority: state_info.clone(),
-                };
-                let cpi_program = ctx.accounts.token_program.to_account_info();
-                let cpi_ctx =
-                    CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-                token::transfer(cpi_ctx, donation_u64)?;
-
-                state.reserve_for_donation = state
-                    .reserve_for_donation
-                    .checked_sub(donation_u64)
-                    .ok_or(EnginesError::MathOverflow)?;
-
-                matched = true;
-                match_amount = donation_u64;
-            }
+        let lamports = Rent::get()?.minimum_balance(UserLockData::SPACE);
+        let refund = user_data_info.lamports().saturating_sub(lamports);
+        user_data_info.resize(UserLockData::SPACE)?;
+        **user_data_info.try_borrow_mut_lamports()? -= refund;
+        **ctx.accounts.user.to_account_info().try_borrow_mut_lamports()? += refund;
+
+        let user_locks = UserLockData {
+            owner: user_key,
+            lock_count: legacy.locks.len() as u64,
+            active_locks: open_locks.len() as u64,
+            total_locked,
+            total_donated: legacy.total_donated,
+            htg_badge_count: legacy.htg_badge_count,
+        };
+        let mut data = user_data_info.try_borrow_mut_data()?;
+        user_locks.try_serialize(&mut &mut data[..])?;
 
-            // Send donation (user share)
-            {
-                let cpi_accounts = Transfer {
-                    from: ctx.accounts.vault.to_account_info(),
-                    to: ctx.accounts.zcw_token_account.to_account_info(),
-                    authority: state_info.clone(),
-                };
-                let cpi_program = ctx.accounts.token_program.to_account_info();
-                let cpi_ctx =
-                    CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-                token::transfer(cpi_ctx, donation_u64)?;
-            }
+        emit!(UserLocksMigrated {
+            user: user_key,
+            active_locks: user_locks.active_locks,
+            total_locked,
+        });
 
-            // Return remaining tokens to user
-            {
-                let cpi_accounts = Transfer {
-                    from: ctx.accounts.vault.to_account_info(),
-                    to: ctx.accounts.user_token_account.to_account_info(),
-                    authority: state_info.clone(),
-                };
-                let cpi_program = ctx.accounts.token_program.to_account_info();
-                let cpi_ctx =
-                    CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-                token::transfer(cpi_ctx, to_user)?;
-            }
+        Ok(())
+    }
+}
 
-            // Update per-user counters (drop lock borrow first)
-            let donation_for_user = donation_u64;
-            let lock_id = lock.id;
-            drop(lock);
+/// Badge NFT accounts for one unlock, from `UnlockTokens` or a remaining accounts chunk
+struct BadgeAccounts<'info> {
+    badge_mint: AccountInfo<'info>,
+    user_badge_token_account: AccountInfo<'info>,
+    metadata_account: AccountInfo<'info>,
+    master_edition: AccountInfo<'info>,
+}
 
-            let user_locks_outer = &mut ctx.accounts.user_data;
-            user_locks_outer.total_donated = user_locks_outer
-                .total_donated
-                .checked_add(donation_for_user)
-                .ok_or(EnginesError::MathOverflow)?;
-            user_locks_outer.htg_badge_count = user_locks_outer
-                .htg_badge_count
-                .checked_add(1)
-                .ok_or(EnginesError::MathOverflow)?;
+/// Helper: unlock a matured lock position (with NFT metadata selection)
+/// The caller closes the lock PDA afterwards
+fn unlock_position<'info>(
+    accounts: &mut UnlockCommon<'info>,
+    lock: &LockPosition,
+    badge: &BadgeAccounts<'info>,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    // vault balance changes with every unlock in a batch
+    accounts.vault.reload()?;
+    let state_info = accounts.state.to_account_info();
+    let state = &mut accounts.state;
+
+    require!(
+        is_matured(lock.start, lock.duration, clock.unix_timestamp as u64),
+        EnginesError::LockNotMatured
+    );
+
+    let rate = state.donation_rates[lock.period as usize];
+    let donation = (lock.amount as u128 * rate as u128) / state.scale as u128;
+    let donation_u64 = u64::try_from(donation).map_err(|_| EnginesError::AmountTooLarge)?;
+    let to_user = lock
+        .amount
+        .checked_sub(donation_u64)
+        .ok_or(EnginesError::MathOverflow)?;
+
+    state.total_locked = state
+        .total_locked
+        .checked_sub(lock.amount)
+        .ok_or(EnginesError::MathOverflow)?;
+    state.total_unlocked = state
+        .total_unlocked
+        .checked_add(to_user)
+        .ok_or(EnginesError::MathOverflow)?;
+
+    let authority_key = state.authority;
+    let bump = state.bump;
+    let seeds: &[&[u8]] = &[b"engines_state".as_ref(), authority_key.as_ref(), &[bump]];
+    let signer_seeds: &[&[&[u8]]] = &[seeds];
+
+    let mut matched = false;
+    let mut match_amount = 0u64;
+
+    // Matching donation
+    if matches!(lock.period, Period::D180 | Period::D365) {
+        let available = free_treasury(accounts.vault.amount, state.total_locked);
+        require!(
+            available >= donation_u64,
+            EnginesError::InsufficientMatchingTreasury
+        );
 
-            // === Mint NFT Badge with milestone-based URI ===
-            let badge_id = state.next_badge_id;
-            state.next_badge_id = state
-                .next_badge_id
-                .checked_add(1)
-                .ok_or(EnginesError::MathOverflow)?;
+        let cpi_accounts = Transfer {
+            from: accounts.vault.to_account_info(),
+            to: accounts.zcw_token_account.to_account_info(),
+            authority: state_info.clone(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, donation_u64)?;
 
-            // choose URI based on lock.period
-            let uri: String = match ctx.accounts.user_data.locks[index].period {
-                Period::D30 => state.uri_30_days.clone(),
-                Period::D60 => state.uri_60_days.clone(),
-                Period::D180 => state.uri_180_days.clone(),
-                Period::D355 => state.uri_365_days.clone(),
-            };
+        state.reserve_for_donation = state
+            .reserve_for_donation
+            .checked_sub(donation_u64)
+            .ok_or(EnginesError::MathOverflow)?;
 
-            // Create metadata account (Metaplex)
-            let cpi_accounts = CreateMetadataAccountsV3 {
-                metadata: ctx.accounts.metadata_account.to_account_info(),
-                mint: ctx.accounts.badge_mint.to_account_info(),
-                mint_authority: state_info.clone(),
-                update_authority: state_info.clone(),
-                payer: ctx.accounts.user.to_account_info(),
-                system_program: ctx.accounts.system_program.to_account_info(),
-                rent: ctx.accounts.rent.to_account_info(),
-            };
-            let cpi_program = ctx.accounts.metadata_program.to_account_info();
-            let cpi_ctx =
-                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        matched = true;
+        match_amount = donation_u64;
+    }
 
-            create_metadata_accounts_v3(
-                cpi_ctx,
-                DataV2 {
-                    name: format!("HTG Badge #{}", badge_id),
-                    symbol: "HTGB".to_string(),
-                    uri,
-                    seller_fee_basis_points: 0,
-                    creators: None,
-                    collection: None,
-                    uses: None,
-                },
-                true,
-                true,
-                None,
-            )?;
+    // Send donation (user share)
+    {
+        let cpi_accounts = Transfer {
+            from: accounts.vault.to_account_info(),
+            to: accounts.zcw_token_account.to_account_info(),
+            authority: state_info.clone(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, donation_u64)?;
+    }
 
-            // Mint 1 NFT to user's ATA
-            let mint_accounts = MintTo {
-                mint: ctx.accounts.badge_mint.to_account_info(),
-                to: ctx.accounts.user_badge_token_account.to_account_info(),
-                authority: state_info.clone(),
-            };
-            let mint_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                mint_accounts,
-                signer_seeds,
-            );
-            token::mint_to(mint_ctx, 1)?;
-
-            emit!(TokensUnlocked {
-                user: ctx.accounts.user.key(),
-                lock_id,
-                returned_to_user: to_user,
-                donation_to_zcw: donation_for_user,
-                matched,
-                match_amount,
-                badge_id,
-            });
-        }
+    // Return remaining tokens to user
+    {
+        let cpi_accounts = Transfer {
+            from: accounts.vault.to_account_info(),
+            to: accounts.user_token_account.to_account_info(),
+            authority: state_info.clone(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, to_user)?;
     }
+
+    // Update per-user counters
+    let user_locks = &mut accounts.user_data;
+    user_locks.active_locks = user_locks
+        .active_locks
+        .checked_sub(1)
+        .ok_or(EnginesError::MathOverflow)?;
+    user_locks.total_locked = user_locks
+        .total_locked
+        .checked_sub(lock.amount)
+        .ok_or(EnginesError::MathOverflow)?;
+    user_locks.total_donated = user_locks
+        .total_donated
+        .checked_add(donation_u64)
+        .ok_or(EnginesError::MathOverflow)?;
+    user_locks.htg_badge_count = user_locks
+        .htg_badge_count
+        .checked_add(1)
+        .ok_or(EnginesError::MathOverflow)?;
+
+    // === Mint NFT Badge with milestone-based URI ===
+    let badge_id = state.next_badge_id;
+    state.next_badge_id = state
+        .next_badge_id
+        .checked_add(1)
+        .ok_or(EnginesError::MathOverflow)?;
+
+    let state = &accounts.state;
+
+    // choose URI based on lock.period
+    let uri: String = match lock.period {
+        Period::D30 => state.uri_30_days.clone(),
+        Period::D60 => state.uri_60_days.clone(),
+        Period::D180 => state.uri_180_days.clone(),
+        Period::D365 => state.uri_365_days.clone(),
+    };
+
+    let state_key = state_info.key();
+    let user_key = accounts.user.key();
+    let lock_id = lock.id.to_le_bytes();
+    let programs = BadgePrograms {
+        payer: accounts.caller.to_account_info(),
+        user: accounts.user.to_account_info(),
+        state: state_info.clone(),
+        token_program: accounts.token_program.to_account_info(),
+        associated_token_program: accounts.associated_token_program.to_account_info(),
+        metadata_program: accounts.metadata_program.to_account_info(),
+        system_program: accounts.system_program.to_account_info(),
+        rent: accounts.rent.to_account_info(),
+    };
+    mint_badge(
+        &programs,
+        badge,
+        signer_seeds,
+        &[b"badge".as_ref(), state_key.as_ref(), user_key.as_ref(), lock_id.as_ref()],
+        badge_id,
+        DataV2 {
+            name: format!("HTG Badge #{}", badge_id),
+            symbol: "HTGB".to_string(),
+            uri,
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        },
+        state.soulbound_badges,
+    )?;
+
+    emit!(TokensUnlocked {
+        user: accounts.user.key(),
+        lock_id: lock.id,
+        returned_to_user: to_user,
+        donation_to_zcw: donation_u64,
+        matched,
+        match_amount,
+        badge_id,
+    });
+
     Ok(())
 }
 
+/// Payer, recipient and programs needed to mint a badge
+struct BadgePrograms<'info> {
+    payer: AccountInfo<'info>,
+    user: AccountInfo<'info>,
+    state: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    associated_token_program: AccountInfo<'info>,
+    metadata_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    rent: AccountInfo<'info>,
+}
+
+/// Helper: mint a 1/1 badge NFT to the user (mint PDA at `mint_seeds`, ATA, metadata, master edition),
+/// frozen first when `soulbound`
+fn mint_badge<'info>(
+    programs: &BadgePrograms<'info>,
+    badge: &BadgeAccounts<'info>,
+    signer_seeds: &[&[&[u8]]],
+    mint_seeds: &[&[u8]],
+    badge_id: u64,
+    data: DataV2,
+    soulbound: bool,
+) -> Result<()> {
+    init_badge_accounts(programs, badge, mint_seeds)?;
+
+    // Create metadata account (Metaplex)
+    let cpi_accounts = CreateMetadataAccountsV3 {
+        metadata: badge.metadata_account.clone(),
+        mint: badge.badge_mint.clone(),
+        mint_authority: programs.state.clone(),
+        update_authority: programs.state.clone(),
+        payer: programs.payer.clone(),
+        system_program: programs.system_program.clone(),
+        rent: programs.rent.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        programs.metadata_program.clone(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    create_metadata_accounts_v3(cpi_ctx, data, true, true, None)?;
+
+    // Mint 1 NFT to user's ATA
+    let mint_accounts = MintTo {
+        mint: badge.badge_mint.clone(),
+        to: badge.user_badge_token_account.clone(),
+        authority: programs.state.clone(),
+    };
+    let mint_ctx = CpiContext::new_with_signer(
+        programs.token_program.clone(),
+        mint_accounts,
+        signer_seeds,
+    );
+    token::mint_to(mint_ctx, 1)?;
+
+    if soulbound {
+        freeze_badge(programs, badge, signer_seeds)?;
+    }
+
+    // Create master edition (max supply 0: a true 1/1), which takes over mint and freeze authority
+    let cpi_accounts = CreateMasterEditionV3 {
+        edition: badge.master_edition.clone(),
+        mint: badge.badge_mint.clone(),
+        update_authority: programs.state.clone(),
+        mint_authority: programs.state.clone(),
+        payer: programs.payer.clone(),
+        metadata: badge.metadata_account.clone(),
+        token_program: programs.token_program.clone(),
+        system_program: programs.system_program.clone(),
+        rent: programs.rent.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        programs.metadata_program.clone(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    create_master_edition_v3(cpi_ctx, Some(0))?;
+
+    if soulbound {
+        emit!(BadgeSoulbound {
+            user: programs.user.key(),
+            badge_id,
+            badge_mint: badge.badge_mint.key(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Helper: create the badge mint PDA at `mint_seeds` with decimals 0 and the state PDA
+/// as mint and freeze authority, then the user's badge ATA
+fn init_badge_accounts<'info>(
+    programs: &BadgePrograms<'info>,
+    badge: &BadgeAccounts<'info>,
+    mint_seeds: &[&[u8]],
+) -> Result<()> {
+    let state_key = programs.state.key();
+    let (badge_mint_key, badge_bump) = Pubkey::find_program_address(mint_seeds, &crate::ID);
+    require_keys_eq!(
+        badge.badge_mint.key(),
+        badge_mint_key,
+        EnginesError::InvalidBadgeMint
+    );
+    let bump = [badge_bump];
+    let mut seeds = mint_seeds.to_vec();
+    seeds.push(&bump);
+    create_pda_account(
+        &programs.payer,
+        &badge.badge_mint,
+        &programs.system_program,
+        Mint::LEN,
+        programs.token_program.key,
+        &[&seeds],
+    )?;
+
+    let cpi_accounts = InitializeMint2 {
+        mint: badge.badge_mint.clone(),
+    };
+    let cpi_ctx = CpiContext::new(programs.token_program.clone(), cpi_accounts);
+    token::initialize_mint2(cpi_ctx, 0, &state_key, Some(&state_key))?;
+
+    // ATA program checks the address against (user, badge_mint)
+    let cpi_accounts = associated_token::Create {
+        payer: programs.payer.clone(),
+        associated_token: badge.user_badge_token_account.clone(),
+        authority: programs.user.clone(),
+        mint: badge.badge_mint.clone(),
+        system_program: programs.system_program.clone(),
+        token_program: programs.token_program.clone(),
+    };
+    let cpi_ctx = CpiContext::new(programs.associated_token_program.clone(), cpi_accounts);
+    associated_token::create(cpi_ctx)?;
+
+    Ok(())
+}
+
+/// Helper: create the PDA `account` with `space` bytes owned by `owner`, `payer` funding its rent.
+/// A pre-funded address is topped up, allocated and assigned like Anchor's `init` does
+fn create_pda_account<'info>(
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    system_program_info: &AccountInfo<'info>,
+    space: usize,
+    owner: &Pubkey,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let lamports = Rent::get()?.minimum_balance(space);
+    let current_lamports = account.lamports();
+    if current_lamports == 0 {
+        let cpi_accounts = system_program::CreateAccount {
+            from: payer.clone(),
+            to: account.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            system_program_info.clone(),
+            cpi_accounts,
+            signer_seeds,
+        );
+        return system_program::create_account(cpi_ctx, lamports, space as u64, owner);
+    }
+
+    let top_up = lamports.saturating_sub(current_lamports);
+    if top_up > 0 {
+        let cpi_accounts = system_program::Transfer {
+            from: payer.clone(),
+            to: account.clone(),
+        };
+        let cpi_ctx = CpiContext::new(system_program_info.clone(), cpi_accounts);
+        system_program::transfer(cpi_ctx, top_up)?;
+    }
+
+    let cpi_accounts = system_program::Allocate {
+        account_to_allocate: account.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        system_program_info.clone(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    system_program::allocate(cpi_ctx, space as u64)?;
+
+    let cpi_accounts = system_program::Assign {
+        account_to_assign: account.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        system_program_info.clone(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    system_program::assign(cpi_ctx, owner)
+}
+
+/// Helper: freeze the user's badge token account so the badge cannot be transferred
+/// Runs before the master edition is created, which moves mint and freeze authority to the edition PDA.
+/// Unlike `soul_bound::mint_soulbound_nft` they are not set to `None` (the edition needs them), but the
+/// handover is equivalent: Token Metadata never mints more of a max-supply-0 edition and only thaws via
+/// `ThawDelegatedAccount`, which needs an owner-approved delegate, and SPL Token rejects `Approve` on a
+/// frozen account
+fn freeze_badge<'info>(
+    programs: &BadgePrograms<'info>,
+    badge: &BadgeAccounts<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let cpi_accounts = FreezeAccount {
+        account: badge.user_badge_token_account.clone(),
+        mint: badge.badge_mint.clone(),
+        authority: programs.state.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(programs.token_program.clone(), cpi_accounts, signer_seeds);
+    token::freeze_account(cpi_ctx)
+}
+
+/// Helper: whole zktc to base units
+fn to_base_units(amount: u64, decimals: u8) -> Result<u64> {
+    10u64
+        .checked_pow(decimals as u32)
+        .and_then(|unit| amount.checked_mul(unit))
+        .ok_or(error!(EnginesError::MathOverflow))
+}
+
 /// Helper functions
-fn is_matured(start: u64, period: Period, durations: &[u64; 4], current_time: u64) -> bool {
-    current_time >= start + durations[period as usize]
+fn is_matured(start: u64, duration: u64, current_time: u64) -> bool {
+    current_time >= start.saturating_add(duration)
 }
 
-fn free_treasury(vault_balance: u64, total_locked: u64) -> u64 {
-    if vault_balance > total_locked {
-        vault_balance - total_locked
-    } else {
-        0
+/// Penalty = amount * penalty_bps / scale * time left / duration, rounded up
+fn early_unlock_penalty(lock: &LockPosition, penalty_bps: u16, scale: u16, current_time: u64) -> Result<u64> {
+    let end = lock.start.saturating_add(lock.duration);
+    let remaining = end.saturating_sub(current_time).min(lock.duration);
+    let penalty = (lock.amount as u128)
+        .checked_mul(penalty_bps as u128)
+        .and_then(|p| p.checked_mul(remaining as u128))
+        .ok_or(EnginesError::MathOverflow)?
+        .div_ceil(scale as u128 * lock.duration.max(1) as u128);
+    Ok(u64::try_from(penalty).map_err(|_| EnginesError::AmountTooLarge)?)
+}
+
+fn validate_period_durations(durations: &[u64; 4]) -> Result<()> {
+    require!(durations[0] > 0, EnginesError::InvalidPeriodDurations);
+    for pair in durations.windows(2) {
+        require!(pair[0] < pair[1], EnginesError::InvalidPeriodDurations);
     }
+    Ok(())
 }
 
-fn available_excess(vault_balance: u64, total_locked: u64, reserve_for_donation: u64) -> u64 {
-    let after_locked = if vault_balance > total_locked {
-        vault_balance - total_locked
-    } else {
-        0
-    };
+fn free_treasury(vault_balance: u64, total_locked: u64) -> u64 {
+    vault_balance.saturating_sub(total_locked)
+}
 
-    if after_locked > reserve_for_donation {
-        after_locked - reserve_for_donation
-    } else {
-        0
-    }
+fn available_excess(vault_balance: u64, total_locked: u64, reserve_for_donation: u64) -> u64 {
+    vault_balance
+        .saturating_sub(total_locked)
+        .saturating_sub(reserve_for_donation)
 }
 
 // Account Structures
@@ -604,7 +1357,7 @@ pub struct LockTokens<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
-    #[account(mut)]
+    #[account(mut, has_one = vault @ EnginesError::InvalidVault)]
     pub state: Account<'info, EnginesState>,
 
     #[account(
@@ -616,6 +1369,21 @@ pub struct LockTokens<'info> {
     )]
     pub user_data: Account<'info, UserLockData>,
 
+    /// One PDA per lock, seeded by (state, user, lock_id)
+    #[account(
+        init,
+        payer = user,
+        space = LockPosition::SPACE,
+        seeds = [
+            b"lock",
+            state.key().as_ref(),
+            user.key().as_ref(),
+            state.next_lock_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub lock_position: Account<'info, LockPosition>,
+
     #[account(
         mut,
         constraint = user_token_account.mint == state.zktc_mint,
@@ -623,10 +1391,7 @@ pub struct LockTokens<'info> {
     )]
     pub user_token_account: Account<'info, TokenAccount>,
 
-    #[account(
-        mut,
-        constraint = vault.mint == state.zktc_mint
-    )]
+    #[account(mut)]
     pub vault: Account<'info, TokenAccount>,
 
     pub token_program: Program<'info, Token>,
@@ -634,12 +1399,24 @@ pub struct LockTokens<'info> {
     pub rent: Sysvar<'info, Rent>,
 }
 
+/// Accounts shared by `unlock_tokens` and `unlock_all_matured`
 #[derive(Accounts)]
-pub struct UnlockTokens<'info> {
+pub struct UnlockCommon<'info> {
+    /// Lock owner or keeper; pays for the badge accounts
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub caller: Signer<'info>,
 
-    #[account(mut, has_one = authority)]
+    /// Lock owner; receives the tokens and the lock PDA rent
+    #[account(mut)]
+    pub user: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        has_one = vault,
+        constraint = caller.key() == user.key()
+            || (state.keeper != Pubkey::default() && caller.key() == state.keeper)
+            @ EnginesError::Unauthorized
+    )]
     pub state: Account<'info, EnginesState>,
 
     #[account(
@@ -649,36 +1426,89 @@ pub struct UnlockTokens<'info> {
     )]
     pub user_data: Account<'info, UserLockData>,
 
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = user_token_account.mint == state.zktc_mint,
+        constraint = user_token_account.owner == user.key()
+    )]
     pub user_token_account: Account<'info, TokenAccount>,
 
     #[account(mut)]
     pub vault: Account<'info, TokenAccount>,
 
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = zcw_token_account.mint == state.zktc_mint,
+        constraint = zcw_token_account.owner == state.zcw
+    )]
     pub zcw_token_account: Account<'info, TokenAccount>,
 
-    /// Badge NFT mint (must be provided / derived by client)
+    /// Programs
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub metadata_program: Program<'info, Metadata>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(lock_id: u64)]
+pub struct UnlockTokens<'info> {
+    pub common: UnlockCommon<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"lock",
+            common.state.key().as_ref(),
+            common.user.key().as_ref(),
+            lock_id.to_le_bytes().as_ref()
+        ],
+        bump = lock_position.bump,
+        constraint = lock_position.state == common.state.key() @ EnginesError::InvalidLockState
+    )]
+    pub lock_position: Account<'info, LockPosition>,
+
+    /// CHECK: Badge NFT mint PDA seeded by (b"badge", state, user, lock_id), created and checked in init_badge_accounts
     #[account(mut)]
-    pub badge_mint: Account<'info, Mint>,
+    pub badge_mint: UncheckedAccount<'info>,
 
-    /// User’s ATA for the badge NFT
+    /// CHECK: User’s ATA for the badge NFT, created by the associated token program
     #[account(mut)]
-    pub user_badge_token_account: Account<'info, TokenAccount>,
+    pub user_badge_token_account: UncheckedAccount<'info>,
 
-    /// Metadata PDA for badge NFT (UncheckedAccount)
+    /// CHECK: Metadata PDA for badge NFT, checked by the metadata program
     #[account(mut)]
     pub metadata_account: UncheckedAccount<'info>,
 
-    /// Programs
-    pub token_program: Program<'info, Token>,
-    pub metadata_program: Program<'info, Metadata>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-
-    /// Authority signer PDA
+    /// CHECK: Master edition PDA for badge NFT, checked by the metadata program
     #[account(mut)]
-    pub authority: Signer<'info>,
+    pub master_edition: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(lock_id: u64)]
+pub struct EarlyUnlock<'info> {
+    #[account(constraint = common.caller.key() == common.user.key() @ EnginesError::Unauthorized)]
+    pub common: UnlockCommon<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"lock",
+            common.state.key().as_ref(),
+            common.user.key().as_ref(),
+            lock_id.to_le_bytes().as_ref()
+        ],
+        bump = lock_position.bump,
+        constraint = lock_position.state == common.state.key() @ EnginesError::InvalidLockState
+    )]
+    pub lock_position: Account<'info, LockPosition>,
+}
+
+#[derive(Accounts)]
+pub struct UnlockAllMatured<'info> {
+    pub common: UnlockCommon<'info>,
 }
 
 #[derive(Accounts)]
@@ -731,7 +1561,25 @@ pub struct BurnToGiveContext<'info> {
     )]
     pub zcw_token_account: Account<'info, TokenAccount>,
 
+    /// CHECK: Burn badge mint PDA seeded by (b"burn_badge", state, user, burn_badge_count), created and checked in init_badge_accounts
+    #[account(mut)]
+    pub badge_mint: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: User’s ATA for the burn badge, created by the associated token program
+    #[account(mut)]
+    pub user_badge_token_account: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Metadata PDA for the burn badge, checked by the metadata program
+    #[account(mut)]
+    pub metadata_account: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Master edition PDA for the burn badge, checked by the metadata program
+    #[account(mut)]
+    pub master_edition: Option<UncheckedAccount<'info>>,
+
     pub token_program: Program<'info, Token>,
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+    pub metadata_program: Option<Program<'info, Metadata>>,
     pub system_program: Program<'info, System>,
     pub rent: Sysvar<'info, Rent>,
 }
@@ -765,6 +1613,65 @@ pub struct WithdrawExcess<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct MigrateState<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: `EnginesState` in the `LegacyEnginesState` layout, checked and rewritten in migrate_state
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"engines_state", authority.key().as_ref()],
+        bump
+    )]
+    pub state: UncheckedAccount<'info>,
+
+    pub zktc_mint: Account<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateUserLocks<'info> {
+    /// Lock owner or state owner; pays for the lock PDAs
+    #[account(mut)]
+    pub caller: Signer<'info>,
+
+    /// Lock owner; receives the rent freed by shrinking `user_data`
+    #[account(mut)]
+    pub user: SystemAccount<'info>,
+
+    /// State of the program upgrade authority, which the legacy locks were made in
+    #[account(
+        seeds = [b"engines_state", state.authority.as_ref()],
+        bump = state.bump,
+        constraint = caller.key() == user.key() || caller.key() == state.authority
+            @ EnginesError::Unauthorized
+    )]
+    pub state: Account<'info, EnginesState>,
+
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(state.authority)
+            @ EnginesError::InvalidLegacyState
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    /// CHECK: `UserLockData` in the `LegacyUserLockData` layout, checked and rewritten in migrate_user_locks
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"user_locks", user.key().as_ref()],
+        bump
+    )]
+    pub user_data: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
 // State Structures
 #[account]
 pub struct EnginesState {
@@ -778,7 +1685,13 @@ pub struct EnginesState {
     pub next_lock_id: u64,
     pub reserve_for_donation: u64,
     pub paused: bool,
+    pub keeper: Pubkey,
+    pub soulbound_badges: bool,
+    pub zktc_decimals: u8,
+    pub burn_badge_threshold: u64,
+    pub burn_badge_tiers: Vec<BurnBadgeTier>,
     pub donation_rates: [u16; 4],
+    pub early_unlock_penalties: [u16; 4],
     pub scale: u16,
     pub period_durations: [u64; 4],
     pub bump: u8,
@@ -801,7 +1714,13 @@ impl EnginesState {
         8 + // next_lock_id
         8 + // reserve_for_donation
         1 + // paused
+        32 + // keeper
+        1 + // soulbound_badges
+        1 + // zktc_decimals
+        8 + // burn_badge_threshold
+        4 + (MAX_BURN_BADGE_TIERS * BurnBadgeTier::SPACE) + // burn_badge_tiers vector
         8 + // donation_rates (4 * u16)
+        8 + // early_unlock_penalties (4 * u16)
         2 + // scale
         32 + // period_durations (4 * u64)
         1 + // bump
@@ -809,35 +1728,118 @@ impl EnginesState {
         64; // extra padding
 }
 
+/// `EnginesState` before `migrate_state`
+#[derive(AnchorDeserialize)]
+pub struct LegacyEnginesState {
+    pub authority: Pubkey,
+    pub zktc_mint: Pubkey,
+    pub zcw: Pubkey,
+    pub vault: Pubkey,
+    pub total_locked: u64,
+    pub total_unlocked: u64,
+    pub next_badge_id: u64,
+    pub next_lock_id: u64,
+    pub reserve_for_donation: u64,
+    pub paused: bool,
+    pub donation_rates: [u16; 4],
+    pub scale: u16,
+    pub period_durations: [u64; 4],
+    pub bump: u8,
+    pub uri_30_days: String,
+    pub uri_60_days: String,
+    pub uri_180_days: String,
+    pub uri_365_days: String,
+}
+
+/// Default `early_unlock_penalties` in basis points: 5%, 10%, 15%, 20%
+pub const DEFAULT_EARLY_UNLOCK_PENALTIES: [u16; 4] = [500, 1000, 1500, 2000];
+pub const MAX_BURN_BADGE_TIERS: usize = 4;
+/// Default `burn_badge_threshold`, in whole zktc: 10,000 * 10^zktc_decimals base units.
+/// The hardcoded threshold it replaced was 10,000 base units
+pub const DEFAULT_BURN_BADGE_THRESHOLD: u64 = 10_000;
+pub const MAX_URI_LEN: usize = 200;
+
+/// Burn-to-Give badge URI used once the user's cumulative burn reaches `min_total_burned` whole zktc
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct BurnBadgeTier {
+    pub min_total_burned: u64,
+    pub uri: String,
+}
+
+impl BurnBadgeTier {
+    pub const SPACE: usize = 8 + // min_total_burned
+        4 + MAX_URI_LEN; // uri
+}
+
+#[account]
 pub struct LockPosition {
+    pub owner: Pubkey,
+    /// `EnginesState` the lock was made in; only that state's vault pays it out
+    pub state: Pubkey,
     pub id: u64,
     pub amount: u64,
     pub start: u64,
     pub period: Period,
-    pub unlocked: bool,
+    pub duration: u64,
+    pub bump: u8,
+    pub reserved_donation: u64,
+}
+
+impl LockPosition {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // owner
+        32 + // state
+        8 + // id
+        8 + // amount
+        8 + // start
+        1 + // period
+        8 + // duration
+        1 + // bump
+        8; // reserved_donation
 }
 
-pub const MAX_LOCKS_PER_USER: usize = 50;
+// remaining accounts per lock in unlock_all_matured
+pub const ACCOUNTS_PER_UNLOCK: usize = 5;
 
 #[account]
 pub struct UserLockData {
     pub owner: Pubkey,
-    pub locks: Vec<LockPosition>,
+    pub lock_count: u64,
+    pub active_locks: u64,
+    pub total_locked: u64,
     pub total_donated: u64,
     pub htg_badge_count: u64,
 }
 
 impl UserLockData {
-    // conservative sizing
-    pub const EST_LOCK_SIZE: usize = 72; // estimated per-lock bytes (id+amount+start+period+bool + padding)
     pub const SPACE: usize = 8 + // discriminator
         32 + // owner
-        4 + (MAX_LOCKS_PER_USER * Self::EST_LOCK_SIZE) + // locks vector
+        8 + // lock_count
+        8 + // active_locks
+        8 + // total_locked
         8 + // total_donated
         8; // htg_badge_count
 }
 
+/// Lock entry of `LegacyUserLockData`
+#[derive(AnchorDeserialize)]
+pub struct LegacyLockPosition {
+    pub id: u64,
+    pub amount: u64,
+    pub start: u64,
+    pub period: Period,
+    pub unlocked: bool,
+}
+
+/// `UserLockData` before `migrate_user_locks`
+#[derive(AnchorDeserialize)]
+pub struct LegacyUserLockData {
+    pub owner: Pubkey,
+    pub locks: Vec<LegacyLockPosition>,
+    pub total_donated: u64,
+    pub htg_badge_count: u64,
+}
+
 #[account]
 pub struct UserBurnData {
     pub owner: Pubkey,
@@ -859,7 +1861,7 @@ pub enum Period {
     D30,
     D60,
     D180,
-    D355,
+    D365,
 }
 
 // Error Types
@@ -867,6 +1869,7 @@ pub enum Period {
 pub enum EnginesError {
     #[msg("Invalid amount")]
     InvalidAmount,
+    /// No longer returned: unlocked positions are closed. Kept so later error codes keep their values
     #[msg("Already unlocked")]
     AlreadyUnlocked,
     #[msg("Lock not matured")]
@@ -883,8 +1886,39 @@ pub enum EnginesError {
     Paused,
     #[msg("Wait for cooldown")]
     WaitForCooldown,
+    /// No longer returned: each lock has its own PDA. Kept so later error codes keep their values
     #[msg("Too many locks per user")]
     TooManyLocks,
+    #[msg("Invalid remaining accounts")]
+    InvalidRemainingAccounts,
+    #[msg("Only the lock owner or the keeper can unlock")]
+    Unauthorized,
+    #[msg("Lock already matured, use unlock_tokens")]
+    LockAlreadyMatured,
+    #[msg("Penalty cannot exceed scale")]
+    InvalidPenalty,
+    #[msg("Invalid badge mint")]
+    InvalidBadgeMint,
+    #[msg("Badge accounts are required")]
+    BadgeAccountsRequired,
+    /// No longer returned: burns are recorded without a badge until tiers are set. Kept so later
+    /// error codes keep their values
+    #[msg("Burn badge tiers not set")]
+    BurnBadgeTiersNotSet,
+    #[msg("Burn badge tiers must start at 0, be ascending and have URIs of at most 200 bytes")]
+    InvalidBurnBadgeTiers,
+    #[msg("Period durations must be non-zero and increasing")]
+    InvalidPeriodDurations,
+    #[msg("Account already uses the current layout")]
+    AlreadyMigrated,
+    #[msg("Invalid zktc mint")]
+    InvalidZktcMint,
+    #[msg("Vault is not the state vault")]
+    InvalidVault,
+    #[msg("Lock belongs to another state")]
+    InvalidLockState,
+    #[msg("Legacy locks migrate only into the program upgrade authority's state")]
+    InvalidLegacyState,
 }
 
 // Events
@@ -915,6 +1949,22 @@ pub struct TokensUnlocked {
     pub badge_id: u64,
 }
 
+#[event]
+pub struct EarlyUnlocked {
+    pub user: Pubkey,
+    pub lock_id: u64,
+    pub returned_to_user: u64,
+    pub penalty_to_zcw: u64,
+    pub released_reserve: u64,
+}
+
+#[event]
+pub struct BadgeSoulbound {
+    pub user: Pubkey,
+    pub badge_id: u64,
+    pub badge_mint: Pubkey,
+}
+
 #[event]
 pub struct UnlockedAllMatured {
     pub user: Pubkey,
@@ -948,3 +1998,294 @@ pub struct DonationRateUpdated {
     pub old_rate: u16,
     pub new_rate: u16,
 }
+
+#[event]
+pub struct EarlyUnlockPenaltyUpdated {
+    pub period: Period,
+    pub old_penalty: u16,
+    pub new_penalty: u16,
+}
+
+#[event]
+pub struct PeriodDurationsUpdated {
+    pub old_durations: [u64; 4],
+    pub new_durations: [u64; 4],
+}
+
+#[event]
+pub struct KeeperUpdated {
+    pub old_keeper: Pubkey,
+    pub new_keeper: Pubkey,
+}
+
+#[event]
+pub struct SoulboundBadgesUpdated {
+    pub soulbound: bool,
+}
+
+#[event]
+pub struct BurnBadgeThresholdUpdated {
+    /// Whole zktc; a burn qualifies at `new_threshold * 10^zktc_decimals` base units
+    pub old_threshold: u64,
+    pub new_threshold: u64,
+    pub zktc_decimals: u8,
+}
+
+#[event]
+pub struct BurnBadgeTiersUpdated {
+    pub tiers: Vec<BurnBadgeTier>,
+}
+
+#[event]
+pub struct StateMigrated {
+    pub authority: Pubkey,
+    pub state: Pubkey,
+}
+
+#[event]
+pub struct UserLocksMigrated {
+    pub user: Pubkey,
+    pub active_locks: u64,
+    pub total_locked: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::entrypoint::SUCCESS;
+    use anchor_lang::solana_program::program_pack::Pack;
+    use anchor_lang::solana_program::program_stubs;
+    use anchor_spl::token::spl_token;
+    use std::collections::BTreeSet;
+    use std::sync::Once;
+
+    fn lock(amount: u64, start: u64, duration: u64) -> LockPosition {
+        LockPosition {
+            owner: Pubkey::default(),
+            state: Pubkey::default(),
+            id: 0,
+            amount,
+            start,
+            period: Period::D30,
+            duration,
+            bump: 0,
+            reserved_donation: 0,
+        }
+    }
+
+    #[test]
+    fn early_unlock_penalty_shrinks_with_time_left() {
+        // 20% of 1001 over 100 seconds
+        let lock = lock(1001, 1_000, 100);
+        // 200.2 at the start
+        assert_eq!(early_unlock_penalty(&lock, 2000, 10000, 1_000).unwrap(), 201);
+        // 100.1 at the midpoint
+        assert_eq!(early_unlock_penalty(&lock, 2000, 10000, 1_050).unwrap(), 101);
+        // 2.002 one second before maturity
+        assert_eq!(early_unlock_penalty(&lock, 2000, 10000, 1_099).unwrap(), 3);
+        assert_eq!(early_unlock_penalty(&lock, 2000, 10000, 1_100).unwrap(), 0);
+    }
+
+    #[test]
+    fn early_unlock_penalty_is_exact_when_divisible() {
+        let lock = lock(1000, 1_000, 100);
+        assert_eq!(early_unlock_penalty(&lock, 2000, 10000, 1_000).unwrap(), 200);
+        assert_eq!(early_unlock_penalty(&lock, 2000, 10000, 1_050).unwrap(), 100);
+        assert_eq!(early_unlock_penalty(&lock, 2000, 10000, 1_099).unwrap(), 2);
+    }
+
+    /// Lets `try_accounts` run `init` constraints off-chain: rent is `Rent::default()` and
+    /// every CPI succeeds without effect
+    struct OffChainStubs;
+
+    impl program_stubs::SyscallStubs for OffChainStubs {
+        fn sol_get_rent_sysvar(&self, _var_addr: *mut u8) -> u64 {
+            SUCCESS
+        }
+    }
+
+    fn set_stubs() {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(OffChainStubs));
+        });
+    }
+
+    /// Backing storage for an `AccountInfo`; rent exempt, writable
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        is_signer: bool,
+        executable: bool,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, owner: Pubkey, data: Vec<u8>) -> Self {
+            Self {
+                key,
+                owner,
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                is_signer: false,
+                executable: false,
+            }
+        }
+
+        fn signer(key: Pubkey) -> Self {
+            Self {
+                is_signer: true,
+                ..Self::new(key, system_program::ID, Vec::new())
+            }
+        }
+
+        fn program(key: Pubkey) -> Self {
+            Self {
+                executable: true,
+                ..Self::new(key, Pubkey::default(), Vec::new())
+            }
+        }
+
+        fn anchor<T: AccountSerialize>(key: Pubkey, account: &T) -> Self {
+            let mut data = Vec::new();
+            account.try_serialize(&mut data).unwrap();
+            Self::new(key, crate::ID, data)
+        }
+
+        fn token(key: Pubkey, mint: Pubkey, owner: Pubkey, amount: u64) -> Self {
+            let mut data = vec![0; spl_token::state::Account::LEN];
+            spl_token::state::Account {
+                mint,
+                owner,
+                amount,
+                state: spl_token::state::AccountState::Initialized,
+                ..Default::default()
+            }
+            .pack_into_slice(&mut data);
+            Self::new(key, token::ID, data)
+        }
+
+        fn rent_sysvar() -> Self {
+            let rent = Rent::default();
+            let mut data = rent.lamports_per_byte_year.to_le_bytes().to_vec();
+            data.extend_from_slice(&rent.exemption_threshold.to_le_bytes());
+            data.push(rent.burn_percent);
+            Self::new(anchor_lang::solana_program::sysvar::rent::ID, Pubkey::default(), data)
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                true,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                self.executable,
+                0,
+            )
+        }
+    }
+
+    fn engines_state(authority: Pubkey, zktc_mint: Pubkey, vault: Pubkey) -> EnginesState {
+        EnginesState {
+            authority,
+            zktc_mint,
+            zcw: Pubkey::new_unique(),
+            vault,
+            total_locked: 0,
+            total_unlocked: 0,
+            next_badge_id: 0,
+            next_lock_id: 0,
+            reserve_for_donation: 0,
+            paused: false,
+            keeper: Pubkey::default(),
+            soulbound_badges: false,
+            zktc_decimals: 9,
+            burn_badge_threshold: DEFAULT_BURN_BADGE_THRESHOLD,
+            burn_badge_tiers: Vec::new(),
+            donation_rates: [0, 0, 0, 0],
+            early_unlock_penalties: DEFAULT_EARLY_UNLOCK_PENALTIES,
+            scale: 10000,
+            period_durations: [30, 60, 180, 365],
+            bump: 255,
+            uri_30_days: String::new(),
+            uri_60_days: String::new(),
+            uri_180_days: String::new(),
+            uri_365_days: String::new(),
+        }
+    }
+
+    /// Runs the `LockTokens` account checks for a user locking into `vault`, a zktc token
+    /// account the user owns; `None` locks into the state vault
+    fn lock_tokens_accounts(vault: Option<Pubkey>) -> Result<()> {
+        set_stubs();
+        let authority = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let zktc_mint = Pubkey::new_unique();
+        let (state_key, _) =
+            Pubkey::find_program_address(&[b"engines_state", authority.as_ref()], &crate::ID);
+        let (state_vault, _) =
+            Pubkey::find_program_address(&[b"engines_vault", state_key.as_ref()], &crate::ID);
+        let state = engines_state(authority, zktc_mint, state_vault);
+        let (user_data, _) =
+            Pubkey::find_program_address(&[b"user_locks", user.as_ref()], &crate::ID);
+        let (lock_key, _) = Pubkey::find_program_address(
+            &[
+                b"lock",
+                state_key.as_ref(),
+                user.as_ref(),
+                state.next_lock_id.to_le_bytes().as_ref(),
+            ],
+            &crate::ID,
+        );
+        let (vault_key, vault_owner) = match vault {
+            Some(vault) => (vault, user),
+            None => (state_vault, state_key),
+        };
+
+        let mut accounts = vec![
+            TestAccount::signer(user),
+            TestAccount::anchor(state_key, &state),
+            TestAccount::anchor(
+                user_data,
+                &UserLockData {
+                    owner: user,
+                    lock_count: 0,
+                    active_locks: 0,
+                    total_locked: 0,
+                    total_donated: 0,
+                    htg_badge_count: 0,
+                },
+            ),
+            TestAccount::new(lock_key, crate::ID, vec![0; LockPosition::SPACE]),
+            TestAccount::token(Pubkey::new_unique(), zktc_mint, user, 1_000),
+            TestAccount::token(vault_key, zktc_mint, vault_owner, 0),
+            TestAccount::program(token::ID),
+            TestAccount::program(system_program::ID),
+            TestAccount::rent_sysvar(),
+        ];
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        LockTokens::try_accounts(
+            &crate::ID,
+            &mut &infos[..],
+            &[],
+            &mut LockTokensBumps::default(),
+            &mut BTreeSet::new(),
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn lock_tokens_accepts_the_state_vault() {
+        lock_tokens_accounts(None).unwrap();
+    }
+
+    #[test]
+    fn lock_tokens_rejects_a_foreign_vault() {
+        // a zktc account the user controls, so locked tokens would never reach the program
+        let err = lock_tokens_accounts(Some(Pubkey::new_unique())).unwrap_err();
+        assert_eq!(err, EnginesError::InvalidVault.into());
+    }
+}
@@ -7,7 +7,7 @@ pub struct Delist<'info> {
     #[account(mut)]
     maker: Signer<'info>,
     #[account(
-        seeds = [b"marketplace", marketplace.name.as_str().as_bytes()],
+        seeds = [b"marketplace", marketplace.name.as_bytes()],
         bump = marketplace.bump,
     )]
     marketplace: Account<'info, Marketplace>,
@@ -13,7 +13,7 @@ pub struct Initialize<'info> {
         init,
         space = Marketplace::INIT_SPACE,
         payer = admin,
-        seeds = [b"marketplace", name.as_str().as_bytes()],
+        seeds = [b"marketplace", name.as_bytes()],
         bump
     )]
     marketplace: Account<'info, Marketplace>,
@@ -38,7 +38,7 @@ pub struct Initialize<'info> {
 impl<'info> Initialize<'info> {
     pub fn init(&mut self, name: String, fee: u16, bumps: &InitializeBumps) -> Result<()> {
     
-        require!(name.len() > 0 && name.len() < 33, MarketplaceError::NameTooLong);
+        require!(!name.is_empty() && name.len() < 33, MarketplaceError::NameTooLong);
         self.marketplace.set_inner(Marketplace {
             admin: self.admin.key(),
             fee,
@@ -12,7 +12,7 @@ pub struct List<'info> {
     #[account(mut)]
     maker: Signer<'info>,
     #[account(
-        seeds = [b"marketplace", marketplace.name.as_str().as_bytes()],
+        seeds = [b"marketplace", marketplace.name.as_bytes()],
         bump = marketplace.bump,
     )]
     marketplace: Account<'info, Marketplace>,
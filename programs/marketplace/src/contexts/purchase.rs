@@ -11,7 +11,7 @@ pub struct Purchase<'info> {
     maker: SystemAccount<'info>,
     maker_mint: InterfaceAccount<'info, Mint>,
     #[account(
-        seeds = [b"marketplace", marketplace.name.as_str().as_bytes()],
+        seeds = [b"marketplace", marketplace.name.as_bytes()],
         bump = marketplace.bump,
     )]
     marketplace: Account<'info, Marketplace>,
@@ -1,3 +1,6 @@
+// Anchor 0.31 emits its IDL instruction handlers outside the `#[program]` module and they
+// call the deprecated AccountInfo::realloc, so this allow cannot be scoped to that module.
+#![allow(deprecated)]
 use anchor_lang::prelude::*;
 
 declare_id!("76sgJHKdFqnRQ4sheZzjbNFdqFKvS4akdTbeAhsNB3BA");
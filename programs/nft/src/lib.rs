@@ -1,3 +1,6 @@
+// Anchor 0.31 emits its IDL instruction handlers outside the `#[program]` module and they
+// call the deprecated AccountInfo::realloc, so this allow cannot be scoped to that module.
+#![allow(deprecated)]
 #![allow(clippy::result_large_err)]//Compiler directive: tells Rust/Clippy to ignore warnings about large Result error types.
 
 use {
@@ -1,3 +1,6 @@
+// Anchor 0.31 emits its IDL instruction handlers outside the `#[program]` module and they
+// call the deprecated AccountInfo::realloc, so this allow cannot be scoped to that module.
+#![allow(deprecated)]
 #![allow(clippy::result_large_err)]
 
 use {
@@ -6,8 +9,8 @@ use {
         associated_token::AssociatedToken,
         metadata::{
             create_master_edition_v3, create_metadata_accounts_v3,
-            mpl_token_metadata::types::DataV2, CreateMasterEditionV3, CreateMetadataAccountsV3,
-            Metadata,
+            mpl_token_metadata::types::{Creator, DataV2},
+            CreateMasterEditionV3, CreateMetadataAccountsV3, Metadata,
         },
         token::{
             mint_to, freeze_account, set_authority, 
@@ -60,15 +63,12 @@ pub mod soul_bound {
                     rent: ctx.accounts.rent.to_account_info(),
                 },
             ),
-            DataV2 {
-                name: nft_name.clone(),
-                symbol: nft_symbol.clone(),
-                uri: nft_uri.clone(),
-                seller_fee_basis_points: 0, // No royalties for soulbound NFTs
-                creators: None,
-                collection: None,
-                uses: None,
-            },
+            soulbound_data(
+                nft_name.clone(),
+                nft_symbol.clone(),
+                nft_uri.clone(),
+                ctx.accounts.payer.key(),
+            ),
             false, // Is mutable - set to false for immutable soulbound NFTs
             true,  // Update authority is signer
             None,  // Collection details
@@ -215,6 +215,24 @@ pub mod soul_bound {
         Ok(())
     }
 }
+/// Metadata of a soulbound NFT: the issuer (payer and update authority, who signs the mint) is
+/// its only creator and is verified, so `dco` can accept it as a KYC credential from that issuer
+pub fn soulbound_data(name: String, symbol: String, uri: String, issuer: Pubkey) -> DataV2 {
+    DataV2 {
+        name,
+        symbol,
+        uri,
+        seller_fee_basis_points: 0, // No royalties for soulbound NFTs
+        creators: Some(vec![Creator {
+            address: issuer,
+            verified: true,
+            share: 100,
+        }]),
+        collection: None,
+        uses: None,
+    }
+}
+
 #[derive(Accounts)]
 #[instruction(nft_name: String, nft_symbol: String, nft_uri: String, recipient: Pubkey)]
 pub struct CreateSoulboundToken<'info> {
@@ -1,15 +1,16 @@
 
 //import npm packages
-import * as anchor from "@project-serum/anchor";
+import * as anchor from "@coral-xyz/anchor";
 import { Connection, PublicKey, Keypair } from "@solana/web3.js";
 import { getAssociatedTokenAddress,
     getOrCreateAssociatedTokenAccount,createMint ,
     TOKEN_PROGRAM_ID,getAccount} from "@solana/spl-token";
 import * as fs from "fs";
 import * as path from "path";
+import { createHash } from "crypto";
 import "dotenv/config";
 import { fileURLToPath } from "url";
-import {  Wallet } from "@project-serum/anchor";
+import {  Wallet } from "@coral-xyz/anchor";
 import { BN } from "bn.js"; // ⚡ safest — BN is actually from bn.js
 
 
@@ -25,12 +26,15 @@ const __filename = fileURLToPath(import.meta.url);
 const __dirname = path.dirname(__filename);
 const idlPath = path.join(__dirname, "./dco.json");
 const idl = JSON.parse(fs.readFileSync(idlPath, "utf-8"));
-// import idl from '../target/idl/zktc.json' assert {type : "json"};
-if (!idl.version || !idl.instructions || !idl.accounts) {
-  throw new Error("❌ IDL is invalid: missing version, instructions, or accounts");
+// regenerate with `anchor build` and copy target/idl/dco.json here
+if (!idl.address || !idl.metadata?.version || !idl.instructions || !idl.accounts) {
+  throw new Error("❌ IDL is invalid: missing address, version, instructions, or accounts");
+}
+if (idl.address !== PROGRAM_ID.toBase58()) {
+  throw new Error("❌ IDL address does not match PROGRAM_ID");
 }
 
-console.log("✅ IDL is valid, version:", idl.version);
+console.log("✅ IDL is valid, version:", idl.metadata.version);
 
 // Load wallet
 const secretKeyPath = path.join(__dirname, "../new_program-keypair.json");
@@ -45,13 +49,19 @@ const provider = new anchor.AnchorProvider(connection, wallet, {
   preflightCommitment: "confirmed" 
 });
 anchor.setProvider(provider);
-const program = new anchor.Program(idl, PROGRAM_ID, provider);
+const program = new anchor.Program(idl, provider);
 const mint = new PublicKey("Fi7ZwQ3wHDTFDAn5knBrGQ8yMqhw5vgFM9FGFjCADKRn")
 const tokenAccount = new PublicKey("F4zRjkJyB3rdpqsyeaPUbpnSFN5R2FCYGZEBXHDHen8g")
 
 
 const tokenPrice = new BN(1000000); // example 1 ZKTC = 1_000_000 lamports- 0.001 sol
 const dcoEndTime = Math.floor(Date.now() / 1000) + 86400; // 1 day later
+const softCap = new BN(10_000).mul(new BN(1000000000)); // 10k ZKTC
+const hardCap = new BN(100_000).mul(new BN(1000000000)); // 100k ZKTC
+const vestingCliff = new BN(0); // seconds
+const vestingDuration = new BN(30 * 86400); // 30 days
+const tgeUnlockBps = 1000; // 10% unlocked at TGE
+const donationBps = 200; // 2% of each purchase goes to ZCW
 const zcw = new PublicKey("9Sd9LLuR1LLy1MtRvvqUNTDq3arnPptRjLRmWzgM9fCz");
 
 
@@ -72,7 +82,17 @@ async function initialize(){
     console.log("vaultPda",vaultPda)
     
 //     const sig = await program.methods
-//       .initialize(tokenPrice, new BN(dcoEndTime), zcw)
+//       .initialize(
+//         tokenPrice,
+//         new BN(dcoEndTime),
+//         zcw,
+//         softCap,
+//         hardCap,
+//         vestingCliff,
+//         vestingDuration,
+//         tgeUnlockBps,
+//         donationBps
+//       )
 //       .accounts({
 //         owner : wallet.publicKey,
 //         tokenMint : mint,
@@ -162,9 +182,11 @@ const [statePda] = PublicKey.findProgramAddressSync(
 
 // the new releaser wallet address you want to add
 const newReleaser = new PublicKey("DR1RUW23oNWUy4GAuXB7gzsT56PyZ8Tj3a7ZEyfDnXPW");
+// max ZKTC the releaser may release per day (0 = unlimited)
+const dailyLimit = new BN(0);
 
 const sig =await program.methods
-  .addReleaser(newReleaser)
+  .addReleaser(newReleaser, dailyLimit)
   .accounts({
     state: statePda,
     owner: owner, // must sign
@@ -216,27 +238,29 @@ const [vaultPda] = PublicKey.findProgramAddressSync(
   program.programId
 );
 
-// Buyer’s associated token account for ZKTC (must exist)
+// Buyer wallet; released tokens are credited to its vesting account and claimed by the buyer
 const buyerWallet = new PublicKey("DR1RUW23oNWUy4GAuXB7gzsT56PyZ8Tj3a7ZEyfDnXPW");
 
-const buyerTokenAccount = getOrCreateAssociatedTokenAccount(
-  connection,
-  wallet.payer,              // payer (for rent)
-  mint,                  // mint = your pre-deployed ZKTC
-  buyerWallet                // owner of the token account
-);
+// unique reference of the off-chain payment, a release receipt is created per reference
+const paymentRef = Array.from(createHash("sha256").update("payment-0001").digest());
 
 const amount = new BN(100 * 1000000000);   // number of ZKTC tokens to release
-const donationAmount = new BN(2000_000_000); // donation in lamports or fixed units
+const donationOverride = null; // null = donation_bps of the amount
+const maxAllocation = new BN(0); // only checked when an allowlist is set
+const proof = [];
 
+// vesting, contribution and receipt PDAs are resolved from the IDL
 const sig =await program.methods
-  .releaseZktc(amount, donationAmount)
-  .accounts({
+  .releaseZktc(paymentRef, amount, donationOverride, maxAllocation, proof)
+  .accountsPartial({
     state: statePda,
     caller: owner, // must be owner or releaser
+    buyer: buyerWallet,
+    round: null, // active round, required once any round exists
+    referral: null,
+    kycTokenAccount: null, // required when a KYC issuer is set
+    kycMetadata: null,
     vault: vaultPda,
-    buyerTokenAccount: (await buyerTokenAccount).address,
-    tokenProgram: TOKEN_PROGRAM_ID,
   })
   .rpc();
 